@@ -1,3 +1,5 @@
+#![allow(deprecated, clippy::needless_borrow)]
+
 #[macro_use]
 extern crate criterion;
 
@@ -2,4 +2,6 @@ set -e	# stop on failure
 cargo +stable fmt -- --check
 cargo clippy -- -D warnings
 cargo test
+cargo clippy --no-default-features -- -D warnings
+cargo clippy --no-default-features --features alloc -- -D warnings
 echo "PASSED"
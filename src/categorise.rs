@@ -1,9 +1,14 @@
 use super::*;
+use crate::charset::{Charsets, Translate};
+#[cfg(feature = "alloc")]
+use crate::parsing::{abandon_sequence, scan_sequence, Scan, ESC, MAX_PENDING};
+use crate::parsing::{is_sgr, next_sequence};
 
 #[cfg(all(not(feature = "std"), feature = "alloc"))]
 use alloc::vec::Vec;
 
 const SEPARATOR: char = ';';
+const SUB_SEPARATOR: char = ':';
 
 /// Parses the text and returns each formatted slice in order.
 /// The ANSI escape codes are not included in the text slices.
@@ -11,9 +16,10 @@ const SEPARATOR: char = ';';
 /// Each different text slice is returned in order such that the text without the escape characters can be reconstructed.
 /// There is a helper function (`construct_text_no_codes`) on `CategorisedSlices` for this.
 #[deprecated = "please use v3::categorise_text to move to API v3.0. \
-                this function will be removed with v3.0 of cansi"]
+                this function will be removed in a future major version of cansi"]
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
-pub fn categorise_text(text: &str) -> CategorisedSlices {
+pub fn categorise_text(text: &str) -> CategorisedSlices<'_> {
     categorise_text_v3(text)
         .into_iter()
         .map(Into::into)
@@ -25,105 +31,609 @@ pub fn categorise_text(text: &str) -> CategorisedSlices {
 ///
 /// Each different text slice is returned in order such that the text without the escape characters can be reconstructed.
 /// There is a helper function (`construct_text_no_codes`) on `CategorisedSlices` for this.
-pub fn categorise_text_v3(text: &str) -> v3::CategorisedSlices {
-    let matches = parse(text);
+///
+/// Styling is cumulative, as it is in a terminal: each sequence patches the running style, and
+/// only a reset (`0` or an empty parameter) returns it to the defaults.
+///
+/// Characters drawn with the DEC Special Graphics set (see `decode_charsets`) are translated to
+/// Unicode, and the `SO` and `SI` controls which switch sets are removed. A translated character
+/// is a slice of its own, with the `start` and `end` of the original character.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[1m\x1b[31mhi\x1b[22m!");
+/// assert_eq!(slices[0].intensity, Some(Intensity::Bold));
+/// assert_eq!(slices[0].fg, Some(Color::Red));
+/// assert_eq!(slices[1].intensity, Some(Intensity::Normal));
+/// assert_eq!(slices[1].fg, Some(Color::Red));
+/// ```
+#[cfg(feature = "alloc")]
+pub fn categorise_text_v3(text: &str) -> v3::CategorisedSlices<'_> {
+    categorise(text, |_| ())
+}
+
+/// Parses the text and returns each formatted slice in order, along with the escape sequences
+/// that are not styling (such as cursor movement, erasing, or window titles).
+///
+/// Only `SGR` sequences affect the style of the slices, the other sequences are removed from the
+/// text and returned in order, so they can be interpreted separately.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let (slices, controls) = categorise_text_and_controls("\x1b[31mone\x1b[2K\x1b[1Gtwo");
+/// assert_eq!(&construct_text_no_codes(&slices), "onetwo");
+/// assert!(slices.iter().all(|s| s.fg == Some(Color::Red)));
+/// let controls: Vec<_> = controls.iter().map(|m| m.text).collect();
+/// assert_eq!(controls, vec!["\x1b[2K", "\x1b[1G"]);
+/// ```
+#[cfg(feature = "alloc")]
+pub fn categorise_text_and_controls_v3(text: &str) -> (v3::CategorisedSlices<'_>, Vec<Match<'_>>) {
+    let mut controls = Vec::new();
+    let slices = categorise(text, |m| controls.push(m));
+    (slices, controls)
+}
+
+/// Parses the bytes and returns each formatted slice in order, the byte counterpart of
+/// `categorise_text`. The offsets are the same as categorising the text would give, but the
+/// input does not need to be valid UTF-8. Characters drawn with the DEC Special Graphics set are
+/// translated to the UTF-8 bytes of their Unicode characters, as `categorise_text` does.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_bytes(b"\x1b[31mcaf\xe9\x1b[0m \xff");
+/// assert_eq!(slices[0].text, b"caf\xe9");
+/// assert_eq!(slices[0].style.fg, Some(Color::Red));
+/// assert_eq!((slices[1].start, slices[1].end), (13, 15));
+/// ```
+#[cfg(feature = "alloc")]
+pub fn categorise_bytes(bytes: &[u8]) -> v3::CategorisedByteSlices<'_> {
+    categorise_bytes_iter(bytes).collect()
+}
+
+/// Categorise the text, passing any non-styling sequences to `control`.
+#[cfg(feature = "alloc")]
+fn categorise<'a, F>(text: &'a str, control: F) -> v3::CategorisedSlices<'a>
+where
+    F: FnMut(Match<'a>),
+{
+    let mut slices = Vec::new();
+    categorise_with(text, |x| slices.push(x), control);
+    slices
+}
+
+/// Parses the text, passing each formatted slice in order to `slice`, and each escape sequence
+/// that is not styling to `control`.
+///
+/// This is the callback version of `categorise_text_and_controls`, and works without the `std`
+/// or `alloc` features.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let mut len = 0;
+/// let mut controls = 0;
+/// categorise_with(
+///     "\x1b[31mone\x1b[2K\x1b[1Gtwo",
+///     |slice| len += slice.text.len(),
+///     |_| controls += 1,
+/// );
+/// assert_eq!((len, controls), (6, 2));
+/// ```
+pub fn categorise_with<'a, F, C>(text: &'a str, mut slice: F, mut control: C)
+where
+    F: FnMut(v3::CategorisedSlice<'a>),
+    C: FnMut(Match<'a>),
+{
+    let mut charsets = Charsets::default();
+    for token in Tokens::new(text.as_bytes()) {
+        match token {
+            Token::Text(sgr, lo, hi) => {
+                let mut pieces = Translate::new(text.as_bytes(), lo, hi);
+                while let Some((shown, lo, hi)) = pieces.next(&mut charsets) {
+                    let piece = shown.unwrap_or(&text[lo..hi]);
+                    slice(v3::CategorisedSlice::with_sgr(sgr, piece, lo, hi));
+                }
+            }
+            Token::Control(start, end) => {
+                charsets.control(&text.as_bytes()[start..end]);
+                control(Match {
+                    start,
+                    end,
+                    text: &text[start..end],
+                })
+            }
+        }
+    }
+}
+
+/// Lazily categorise the text, returning an iterator over each formatted slice in order.
+///
+/// This is the iterator version of `categorise_text`, useful when only the first few slices are
+/// needed. It does not allocate, so works without the `std` or `alloc` features.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let mut iter = categorise_iter("\x1b[1mhello\x1b[0m, world");
+/// let first = iter.next().unwrap();
+/// assert_eq!(first.text, "hello");
+/// assert_eq!(first.intensity, Some(Intensity::Bold));
+/// assert_eq!(iter.next().unwrap().text, ", world");
+/// assert_eq!(iter.next(), None);
+/// ```
+pub fn categorise_iter(text: &str) -> CategorisedIter<'_> {
+    CategorisedIter {
+        text,
+        tokens: Tokens::new(text.as_bytes()),
+        charsets: Charsets::default(),
+        pieces: None,
+    }
+}
 
-    let mut sgr = SGR::default();
+/// An iterator over the formatted slices of text. See `categorise_iter`.
+#[derive(Debug, Clone)]
+pub struct CategorisedIter<'text> {
+    text: &'text str,
+    tokens: Tokens<'text>,
+    charsets: Charsets,
+    /// The rest of the current text run.
+    pieces: Option<(Style, Translate<'text>)>,
+}
 
-    let mut lo = 0;
+impl<'text> Iterator for CategorisedIter<'text> {
+    type Item = v3::CategorisedSlice<'text>;
 
-    // will always less than or equal to matches + 1 in length, see tests
-    let mut slices: Vec<v3::CategorisedSlice> = Vec::with_capacity(matches.len() + 1);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((sgr, pieces)) = &mut self.pieces {
+                if let Some((shown, lo, hi)) = pieces.next(&mut self.charsets) {
+                    let piece = shown.unwrap_or(&self.text[lo..hi]);
+                    return Some(v3::CategorisedSlice::with_sgr(*sgr, piece, lo, hi));
+                }
+                self.pieces = None;
+            }
 
-    for m in matches {
-        // add in the text before CSI with the previous SGR format
-        if m.start != lo {
-            slices.push(v3::CategorisedSlice::with_sgr(
-                sgr,
-                &text[lo..m.start],
-                lo,
-                m.start,
-            ));
+            match self.tokens.next()? {
+                Token::Text(sgr, lo, hi) => {
+                    self.pieces = Some((sgr, Translate::new(self.text.as_bytes(), lo, hi)));
+                }
+                Token::Control(start, end) => {
+                    self.charsets.control(&self.text.as_bytes()[start..end]);
+                }
+            }
         }
+    }
+}
 
-        sgr = handle_seq(&m);
+/// Lazily categorise the bytes, returning an iterator over each formatted slice in order.
+///
+/// This is the iterator version of `categorise_bytes`, and works without the `std` or `alloc`
+/// features.
+pub fn categorise_bytes_iter(bytes: &[u8]) -> CategorisedByteIter<'_> {
+    CategorisedByteIter {
+        bytes,
+        tokens: Tokens::new(bytes),
+        charsets: Charsets::default(),
+        pieces: None,
+    }
+}
 
-        lo = m.end;
+/// An iterator over the formatted slices of bytes. See `categorise_bytes_iter`.
+#[derive(Debug, Clone)]
+pub struct CategorisedByteIter<'text> {
+    bytes: &'text [u8],
+    tokens: Tokens<'text>,
+    charsets: Charsets,
+    /// The rest of the current text run.
+    pieces: Option<(Style, Translate<'text>)>,
+}
+
+impl<'text> Iterator for CategorisedByteIter<'text> {
+    type Item = v3::CategorisedByteSlice<'text>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((sgr, pieces)) = &mut self.pieces {
+                if let Some((shown, lo, hi)) = pieces.next(&mut self.charsets) {
+                    let piece = shown.map_or(&self.bytes[lo..hi], str::as_bytes);
+                    return Some(v3::CategorisedByteSlice::with_sgr(*sgr, piece, lo, hi));
+                }
+                self.pieces = None;
+            }
+
+            match self.tokens.next()? {
+                Token::Text(sgr, lo, hi) => {
+                    self.pieces = Some((sgr, Translate::new(self.bytes, lo, hi)));
+                }
+                Token::Control(start, end) => {
+                    self.charsets.control(&self.bytes[start..end]);
+                }
+            }
+        }
     }
+}
 
-    if lo != text.len() {
-        slices.push(v3::CategorisedSlice::with_sgr(
+/// A run of text with its style, or a non-styling sequence.
+/// Ranges always fall on the boundaries of escape sequences, so are valid `str` boundaries
+/// if the bytes are valid UTF-8.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Token {
+    Text(Style, usize, usize),
+    Control(usize, usize),
+}
+
+/// Walks the escape sequences of `bytes`, tracking the style.
+#[derive(Debug, Clone)]
+pub(crate) struct Tokens<'a> {
+    bytes: &'a [u8],
+    lo: usize,
+    /// The current style, which can be changed between tokens.
+    pub(crate) sgr: Style,
+    /// A sequence found after a text run, processed on the next call.
+    seq: Option<(usize, usize)>,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self::with_style(bytes, Style::default())
+    }
+
+    /// Walk the bytes starting with the `sgr` style, such as the style at the end of the previous
+    /// chunk.
+    pub(crate) fn with_style(bytes: &'a [u8], sgr: Style) -> Self {
+        Self {
+            bytes,
+            lo: 0,
             sgr,
-            &text[lo..text.len()],
-            lo,
-            text.len(),
-        ));
+            seq: None,
+        }
     }
+}
 
-    slices
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if let Some((start, end)) = self.seq.take() {
+                self.lo = end;
+
+                // only SGR sequences carry styling, other sequences are dropped from the text
+                let seq = &self.bytes[start..end];
+                if is_sgr(seq) {
+                    self.sgr = handle_seq(self.sgr, seq);
+                } else {
+                    return Some(Token::Control(start, end));
+                }
+            }
+
+            let lo = self.lo;
+            match next_sequence(self.bytes, lo) {
+                Some((start, end)) => {
+                    self.seq = Some((start, end));
+                    // the text before the sequence with the previous SGR format
+                    if start != lo {
+                        return Some(Token::Text(self.sgr, lo, start));
+                    }
+                }
+                None if lo != self.bytes.len() => {
+                    self.lo = self.bytes.len();
+                    return Some(Token::Text(self.sgr, lo, self.bytes.len()));
+                }
+                None => return None,
+            }
+        }
+    }
 }
 
-/// Produce an `SGR` from a styling sequence.
+/// A stateful categoriser for text which arrives in chunks, such as the output of a child
+/// process.
+///
+/// The style is carried between chunks, and an escape sequence that is split across chunks is
+/// held over until it is complete. A sequence which is still incomplete after 4096 bytes is
+/// abandoned: those bytes are dropped and the rest is categorised as text. Each call to `feed`
+/// returns the slices within that chunk, with the `start` and `end` offsets relative to the
+/// chunk.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let mut c = Categoriser::new();
+///
+/// let first = c.feed("\x1b[1mhello\x1b[3");
+/// assert_eq!(first.len(), 1);
+/// assert_eq!(first[0].text, "hello");
+/// assert_eq!(first[0].intensity, Some(Intensity::Bold));
 ///
-/// Requires `Match` as we can assume skipping of certain bytes.
-fn handle_seq(m: &Match) -> SGR {
+/// let second = c.feed("1m world");
+/// assert_eq!(second[0].text, " world");
+/// assert_eq!((second[0].start, second[0].end), (2, 8));
+/// assert_eq!(second[0].intensity, Some(Intensity::Bold));
+/// assert_eq!(second[0].fg, Some(Color::Red));
+/// ```
+#[cfg(feature = "alloc")]
+#[derive(Debug, Default, Clone)]
+pub struct Categoriser {
+    sgr: Style,
+    charsets: Charsets,
+    pending: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl Categoriser {
+    /// A new categoriser, starting with the default style.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Categorise the next chunk of text.
+    /// The returned slices' offsets are relative to `chunk`.
+    pub fn feed<'c>(&mut self, chunk: &'c str) -> v3::CategorisedSlices<'c> {
+        let mut slices = Vec::new();
+        self.feed_ranges(chunk.as_bytes(), |sgr, shown, lo, hi| {
+            let piece = shown.unwrap_or(&chunk[lo..hi]);
+            slices.push(v3::CategorisedSlice::with_sgr(sgr, piece, lo, hi));
+        });
+        slices
+    }
+
+    /// Categorise the next chunk of bytes, which do not need to be valid UTF-8.
+    /// The returned slices' offsets are relative to `chunk`.
+    pub fn feed_bytes<'c>(&mut self, chunk: &'c [u8]) -> v3::CategorisedByteSlices<'c> {
+        let mut slices = Vec::new();
+        self.feed_ranges(chunk, |sgr, shown, lo, hi| {
+            let piece = shown.map_or(&chunk[lo..hi], str::as_bytes);
+            slices.push(v3::CategorisedByteSlice::with_sgr(sgr, piece, lo, hi));
+        });
+        slices
+    }
+
+    /// Takes the bytes of an escape sequence which was never terminated.
+    ///
+    /// Call this once the input has ended; `categorise_text` would leave these bytes in the text.
+    /// The style is kept, so the categoriser can continue to be fed.
+    pub fn finish(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.pending)
+    }
+
+    fn feed_ranges<F>(&mut self, chunk: &[u8], mut slice: F)
+    where
+        F: FnMut(Style, Option<&'static str>, usize, usize),
+    {
+        let mut lo = 0;
+
+        // complete any sequence held over from the previous chunk
+        while !self.pending.is_empty() {
+            let held = self.pending.len();
+            self.pending.extend_from_slice(&chunk[lo..]);
+
+            match scan_sequence(&self.pending, 0) {
+                Scan::Complete(end) => {
+                    if is_sgr(&self.pending[..end]) {
+                        self.sgr = handle_seq(self.sgr, &self.pending[..end]);
+                    } else {
+                        self.charsets.control(&self.pending[..end]);
+                    }
+
+                    if end >= held {
+                        lo += end - held;
+                        self.pending.clear();
+                    } else {
+                        // aborted by an ESC that was also held over, which starts the next sequence
+                        self.pending.truncate(held);
+                        self.pending.drain(..end);
+                    }
+                }
+                Scan::Incomplete if self.pending.len() <= MAX_PENDING => return, // all held over
+                Scan::Incomplete => {
+                    lo += abandon_sequence(&self.pending, 0) - held;
+                    self.pending.clear();
+                }
+            }
+        }
+
+        while let Some(start) = chunk[lo..].iter().position(|&b| b == ESC).map(|i| i + lo) {
+            if start != lo {
+                self.pieces(chunk, lo, start, &mut slice);
+            }
+
+            match scan_sequence(chunk, start) {
+                Scan::Complete(end) => {
+                    if is_sgr(&chunk[start..end]) {
+                        self.sgr = handle_seq(self.sgr, &chunk[start..end]);
+                    } else {
+                        self.charsets.control(&chunk[start..end]);
+                    }
+                    lo = end;
+                }
+                Scan::Incomplete if chunk.len() - start <= MAX_PENDING => {
+                    self.pending.extend_from_slice(&chunk[start..]);
+                    return;
+                }
+                Scan::Incomplete => lo = abandon_sequence(chunk, start),
+            }
+        }
+
+        if lo != chunk.len() {
+            self.pieces(chunk, lo, chunk.len(), &mut slice);
+        }
+    }
+
+    /// Pass the pieces of `chunk[lo..hi]` to `slice`, with any translated characters.
+    fn pieces<F>(&mut self, chunk: &[u8], lo: usize, hi: usize, slice: &mut F)
+    where
+        F: FnMut(Style, Option<&'static str>, usize, usize),
+    {
+        let mut pieces = Translate::new(chunk, lo, hi);
+        while let Some((shown, lo, hi)) = pieces.next(&mut self.charsets) {
+            slice(self.sgr, shown, lo, hi);
+        }
+    }
+}
+
+/// Apply a styling sequence on top of the current `Style`.
+///
+/// Requires a sequence which is `is_sgr`, as we can assume skipping of certain bytes and that
+/// the parameters are ASCII.
+fn handle_seq(sgr: Style, seq: &[u8]) -> Style {
     // the slice we want to process is skipped of first two bytes (ESC[) and last byte (terminating byte)
-    let slice = &m.text[2..(m.text.len() - 1)];
-    slice.split(SEPARATOR).fold(SGR::default(), adjust_sgr)
+    let slice = core::str::from_utf8(&seq[2..(seq.len() - 1)]).unwrap_or_default();
+    let mut params = slice.split(SEPARATOR);
+    let mut sgr = sgr;
+
+    while let Some(param) = params.next() {
+        sgr = match param.split_once(SUB_SEPARATOR) {
+            // colon form, sub-parameters are contained within the one parameter (38:5:n)
+            Some((code, sub)) => adjust_sgr_extended(sgr, code, sub_params(sub)),
+            // semicolon form, extended colours consume the following parameters (38;5;n)
+            None if is_extended_colour(param) => adjust_sgr_extended(sgr, param, &mut params),
+            None => adjust_sgr(sgr, param),
+        };
+    }
+
+    sgr
+}
+
+/// Parameters that are followed by an extended colour specification.
+/// `58` (underline colour) is not tracked, but its arguments still need consuming.
+fn is_extended_colour(param: &str) -> bool {
+    matches!(parse_code(param), Some(38 | 48 | 58))
+}
+
+/// Split the `:` sub-parameters of a parameter.
+///
+/// The colon form of a direct colour may carry a colour space id (`38:2:cs:r:g:b`, usually left
+/// empty as in `38:2::r:g:b`), which is skipped so the arguments line up with the `;` form.
+fn sub_params(sub: &str) -> impl Iterator<Item = &str> {
+    let mut params = sub.split(SUB_SEPARATOR);
+    let has_colour_space =
+        parse_code(params.next().unwrap_or_default()) == Some(2) && params.count() == 4;
+
+    sub.split(SUB_SEPARATOR)
+        .enumerate()
+        .filter(move |(i, _)| !(has_colour_space && *i == 1))
+        .map(|(_, x)| x)
+}
+
+/// Apply a parameter which carries arguments, either as `:` sub-parameters or as the
+/// following `;` parameters for the extended colours.
+fn adjust_sgr_extended<'a, I>(mut sgr: Style, code: &str, mut args: I) -> Style
+where
+    I: Iterator<Item = &'a str>,
+{
+    match parse_code(code) {
+        Some(38) => sgr.fg = extended_colour(args).or(sgr.fg),
+        Some(48) => sgr.bg = extended_colour(args).or(sgr.bg),
+        Some(58) => {
+            extended_colour(args);
+        }
+        // underline styles, 4:0 is off and 4:1 to 4:5 are single, double, curly, dotted and dashed
+        Some(4) => {
+            sgr.underline = match args.next().map(parse_code) {
+                None | Some(Some(1..=5)) => Some(true),
+                Some(Some(0)) => Some(false),
+                _ => sgr.underline,
+            }
+        }
+        // other sub-parameters are treated as the main code
+        _ => sgr = adjust_sgr(sgr, code),
+    }
+
+    sgr
 }
 
-/// Apply the style seq to the SGR. Maps decimal numbers according to
+/// Reads the colour following a `38` or `48`, consuming only the arguments that belong to
+/// the colour mode.
+fn extended_colour<'a, I>(mut args: I) -> Option<Color>
+where
+    I: Iterator<Item = &'a str>,
+{
+    match parse_code(args.next()?)? {
+        5 => args.next()?.parse().ok().map(Color::Indexed),
+        2 => {
+            let r = args.next()?.parse().ok();
+            let g = args.next()?.parse().ok();
+            let b = args.next()?.parse().ok();
+            Some(Color::Rgb(r?, g?, b?))
+        }
+        _ => None,
+    }
+}
+
+/// Parse a decimal parameter. An empty parameter is treated as `0`.
+fn parse_code(seq: &str) -> Option<u16> {
+    if seq.is_empty() {
+        Some(0)
+    } else {
+        seq.parse().ok()
+    }
+}
+
+/// Apply the SGR sequence to the style. Maps decimal numbers according to
 /// spec at https://en.wikipedia.org/wiki/ANSI_escape_code#Escape_sequences.
-fn adjust_sgr(mut sgr: SGR, seq: &str) -> SGR {
-    match seq {
-        "0" => return SGR::default(),                    // 0
-        "1" => sgr.intensity = Some(Intensity::Bold),    // 1
-        "2" => sgr.intensity = Some(Intensity::Faint),   // 2
-        "3" => sgr.italic = Some(true),                  // 3
-        "4" => sgr.underline = Some(true),               // 4
-        "5" => sgr.blink = Some(true),                   // 5
-        "7" => sgr.reversed = Some(true),                // 7
-        "8" => sgr.hidden = Some(true),                  // 8
-        "9" => sgr.strikethrough = Some(true),           // 9
-        "22" => sgr.intensity = Some(Intensity::Normal), // 22
-        "23" => sgr.italic = Some(false),                // 23
-        "24" => sgr.underline = Some(false),             // 24
-        "25" => sgr.blink = Some(false),                 // 25
-        "27" => sgr.reversed = Some(false),              // 27
-        "28" => sgr.hidden = Some(false),                // 28
-        "29" => sgr.strikethrough = Some(false),         // 29
-        "30" => sgr.fg = Some(Color::Black),             // 30
-        "31" => sgr.fg = Some(Color::Red),               // 31
-        "32" => sgr.fg = Some(Color::Green),             // 32
-        "33" => sgr.fg = Some(Color::Yellow),            // 33
-        "34" => sgr.fg = Some(Color::Blue),              // 34
-        "35" => sgr.fg = Some(Color::Magenta),           // 35
-        "36" => sgr.fg = Some(Color::Cyan),              // 36
-        "37" => sgr.fg = Some(Color::White),             // 37
-        "40" => sgr.bg = Some(Color::Black),             // 40
-        "41" => sgr.bg = Some(Color::Red),               // 41
-        "42" => sgr.bg = Some(Color::Green),             // 42
-        "43" => sgr.bg = Some(Color::Yellow),            // 43
-        "44" => sgr.bg = Some(Color::Blue),              // 44
-        "45" => sgr.bg = Some(Color::Magenta),           // 45
-        "46" => sgr.bg = Some(Color::Cyan),              // 46
-        "47" => sgr.bg = Some(Color::White),             // 47
-        "90" => sgr.fg = Some(Color::BrightBlack),       // 90
-        "91" => sgr.fg = Some(Color::BrightRed),         // 91
-        "92" => sgr.fg = Some(Color::BrightGreen),       // 92
-        "93" => sgr.fg = Some(Color::BrightYellow),      // 93
-        "94" => sgr.fg = Some(Color::BrightBlue),        // 94
-        "95" => sgr.fg = Some(Color::BrightMagenta),     // 95
-        "96" => sgr.fg = Some(Color::BrightCyan),        // 96
-        "97" => sgr.fg = Some(Color::BrightWhite),       // 97
-        "100" => sgr.bg = Some(Color::BrightBlack),      // 100
-        "101" => sgr.bg = Some(Color::BrightRed),        // 101
-        "102" => sgr.bg = Some(Color::BrightGreen),      // 102
-        "103" => sgr.bg = Some(Color::BrightYellow),     // 103
-        "104" => sgr.bg = Some(Color::BrightBlue),       // 104
-        "105" => sgr.bg = Some(Color::BrightMagenta),    // 105
-        "106" => sgr.bg = Some(Color::BrightCyan),       // 106
-        "107" => sgr.bg = Some(Color::BrightWhite),      // 107
+///
+/// Parameters are decimal, so leading zeros (`01`) are accepted and an empty parameter is
+/// treated as `0`.
+fn adjust_sgr(mut sgr: Style, seq: &str) -> Style {
+    let code = match parse_code(seq) {
+        Some(x) => x,
+        None => return sgr,
+    };
+
+    match code {
+        0 => return Style::default(),
+        1 => sgr.intensity = Some(Intensity::Bold),
+        2 => sgr.intensity = Some(Intensity::Faint),
+        3 => sgr.italic = Some(true),
+        4 => sgr.underline = Some(true),
+        5 => sgr.blink = Some(true),
+        7 => sgr.reversed = Some(true),
+        8 => sgr.hidden = Some(true),
+        9 => sgr.strikethrough = Some(true),
+        22 => sgr.intensity = Some(Intensity::Normal),
+        23 => sgr.italic = Some(false),
+        24 => sgr.underline = Some(false),
+        25 => sgr.blink = Some(false),
+        27 => sgr.reversed = Some(false),
+        28 => sgr.hidden = Some(false),
+        29 => sgr.strikethrough = Some(false),
+        30 => sgr.fg = Some(Color::Black),
+        31 => sgr.fg = Some(Color::Red),
+        32 => sgr.fg = Some(Color::Green),
+        33 => sgr.fg = Some(Color::Yellow),
+        34 => sgr.fg = Some(Color::Blue),
+        35 => sgr.fg = Some(Color::Magenta),
+        36 => sgr.fg = Some(Color::Cyan),
+        37 => sgr.fg = Some(Color::White),
+        39 => sgr.fg = None,
+        40 => sgr.bg = Some(Color::Black),
+        41 => sgr.bg = Some(Color::Red),
+        42 => sgr.bg = Some(Color::Green),
+        43 => sgr.bg = Some(Color::Yellow),
+        44 => sgr.bg = Some(Color::Blue),
+        45 => sgr.bg = Some(Color::Magenta),
+        46 => sgr.bg = Some(Color::Cyan),
+        47 => sgr.bg = Some(Color::White),
+        49 => sgr.bg = None,
+        90 => sgr.fg = Some(Color::BrightBlack),
+        91 => sgr.fg = Some(Color::BrightRed),
+        92 => sgr.fg = Some(Color::BrightGreen),
+        93 => sgr.fg = Some(Color::BrightYellow),
+        94 => sgr.fg = Some(Color::BrightBlue),
+        95 => sgr.fg = Some(Color::BrightMagenta),
+        96 => sgr.fg = Some(Color::BrightCyan),
+        97 => sgr.fg = Some(Color::BrightWhite),
+        100 => sgr.bg = Some(Color::BrightBlack),
+        101 => sgr.bg = Some(Color::BrightRed),
+        102 => sgr.bg = Some(Color::BrightGreen),
+        103 => sgr.bg = Some(Color::BrightYellow),
+        104 => sgr.bg = Some(Color::BrightBlue),
+        105 => sgr.bg = Some(Color::BrightMagenta),
+        106 => sgr.bg = Some(Color::BrightCyan),
+        107 => sgr.bg = Some(Color::BrightWhite),
         _ => (),
     }
 
@@ -131,6 +641,7 @@ fn adjust_sgr(mut sgr: SGR, seq: &str) -> SGR {
 }
 
 #[cfg(test)]
+#[allow(deprecated)]
 mod tests {
     use super::*;
     use colored::Colorize;
@@ -138,8 +649,8 @@ mod tests {
     #[test]
     fn cat_and_matches_len() {
         let txt = "hello";
-        let matches = parse(&txt);
-        let cat = categorise_text(&txt);
+        let matches = parse(txt);
+        let cat = categorise_text(txt);
         assert!(matches.len() + 1 >= cat.len());
 
         let txt = "hello".bright_green();
@@ -174,4 +685,396 @@ mod tests {
             }]
         );
     }
+
+    fn styled(
+        text: &str,
+        start: usize,
+        end: usize,
+        f: impl FnOnce(&mut Style),
+    ) -> v3::CategorisedSlice<'_> {
+        let mut sgr = Style::default();
+        f(&mut sgr);
+        v3::CategorisedSlice::with_sgr(sgr, text, start, end)
+    }
+
+    #[test]
+    fn sgr_accumulates() {
+        let x = categorise_text_v3("\x1b[1m\x1b[31mhi\x1b[4mthere\x1b[mplain");
+        assert_eq!(
+            x,
+            vec![
+                styled("hi", 9, 11, |s| {
+                    s.intensity = Some(Intensity::Bold);
+                    s.fg = Some(Color::Red);
+                }),
+                styled("there", 15, 20, |s| {
+                    s.intensity = Some(Intensity::Bold);
+                    s.fg = Some(Color::Red);
+                    s.underline = Some(true);
+                }),
+                v3::CategorisedSlice::default_style("plain", 23, 28),
+            ]
+        );
+
+        // default colours only reset their own attribute
+        let x = categorise_text_v3("\x1b[1;31;42ma\x1b[39mb\x1b[49mc\x1b[1;mz");
+        assert_eq!(x[1].fg, None);
+        assert_eq!(x[1].bg, Some(Color::Green));
+        assert_eq!(x[1].intensity, Some(Intensity::Bold));
+        assert_eq!(x[2].bg, None);
+        assert_eq!(x[2].intensity, Some(Intensity::Bold));
+        assert_eq!(x[3], v3::CategorisedSlice::default_style("z", 28, 29));
+    }
+
+    #[test]
+    fn split_sequences_from_tools() {
+        // ls --color
+        let ls = "\x1b[0m\x1b[01;34msrc\x1b[0m  \x1b[01;32mrun.sh\x1b[0m\n";
+        let x = categorise_text_v3(ls);
+        assert_eq!(v3::construct_text_no_codes(&x), "src  run.sh\n");
+        assert_eq!(x[0].text, "src");
+        assert_eq!(x[0].fg, Some(Color::Blue));
+        assert_eq!(x[0].intensity, Some(Intensity::Bold));
+        assert_eq!(x[1], v3::CategorisedSlice::default_style("  ", 19, 21));
+        assert_eq!(x[2].text, "run.sh");
+        assert_eq!(x[2].fg, Some(Color::Green));
+        assert_eq!(x[2].intensity, Some(Intensity::Bold));
+
+        // git log --decorate
+        let git = "\x1b[33mcommit 1a2b\x1b[m\x1b[33m (\x1b[m\x1b[1;36mHEAD -> \x1b[m\x1b[1;32mmaster\x1b[m\x1b[33m)\x1b[m";
+        let x = categorise_text_v3(git);
+        assert_eq!(
+            v3::construct_text_no_codes(&x),
+            "commit 1a2b (HEAD -> master)"
+        );
+        let styles: Vec<_> = x.iter().map(|s| (s.text, s.fg, s.intensity)).collect();
+        assert_eq!(
+            styles,
+            vec![
+                ("commit 1a2b", Some(Color::Yellow), None),
+                (" (", Some(Color::Yellow), None),
+                ("HEAD -> ", Some(Color::Cyan), Some(Intensity::Bold)),
+                ("master", Some(Color::Green), Some(Intensity::Bold)),
+                (")", Some(Color::Yellow), None),
+            ]
+        );
+
+        // rustc diagnostics
+        let rustc =
+            "\x1b[0m\x1b[1m\x1b[31merror[E0308]\x1b[0m\x1b[0m\x1b[1m: mismatched types\x1b[0m";
+        let x = categorise_text_v3(rustc);
+        assert_eq!(x.len(), 2);
+        assert_eq!(x[0].text, "error[E0308]");
+        assert_eq!(x[0].fg, Some(Color::Red));
+        assert_eq!(x[0].intensity, Some(Intensity::Bold));
+        assert_eq!(x[1].text, ": mismatched types");
+        assert_eq!(x[1].fg, None);
+        assert_eq!(x[1].intensity, Some(Intensity::Bold));
+    }
+
+    #[test]
+    fn indexed_colours() {
+        let x = categorise_text_v3("\x1b[38;5;208ma\x1b[48;5;236mb\x1b[38:5:9;1mc");
+        assert_eq!(x[0].fg, Some(Color::Indexed(208)));
+        assert_eq!(x[0].blink, None); // 5 is not treated as blink
+        assert_eq!(x[1].fg, Some(Color::Indexed(208)));
+        assert_eq!(x[1].bg, Some(Color::Indexed(236)));
+        assert_eq!(x[2].fg, Some(Color::Indexed(9)));
+        assert_eq!(x[2].bg, Some(Color::Indexed(236)));
+        assert_eq!(x[2].intensity, Some(Intensity::Bold));
+
+        // parameters following the colour are still applied
+        let x = categorise_text_v3("\x1b[1;38;5;1;4mz");
+        assert_eq!(x[0].fg, Some(Color::Indexed(1)));
+        assert_eq!(x[0].intensity, Some(Intensity::Bold));
+        assert_eq!(x[0].underline, Some(true));
+
+        // underline colour is consumed but not tracked
+        let x = categorise_text_v3("\x1b[58;5;3;4mz");
+        assert_eq!(x[0].fg, None);
+        assert_eq!(x[0].italic, None);
+        assert_eq!(x[0].underline, Some(true));
+
+        // out of range or truncated indices leave the colour unchanged
+        let x = categorise_text_v3("\x1b[31;38;5;256mz\x1b[38;5mzz");
+        assert_eq!(x[0].fg, Some(Color::Red));
+        assert_eq!(x[1].fg, Some(Color::Red));
+    }
+
+    #[test]
+    fn rgb_colours() {
+        let x = categorise_text_v3("\x1b[38;2;255;0;0ma\x1b[48;2;1;2;3;1mb");
+        assert_eq!(x[0].fg, Some(Color::Rgb(255, 0, 0)));
+        assert_eq!(x[0].intensity, None);
+        assert_eq!(x[1].fg, Some(Color::Rgb(255, 0, 0)));
+        assert_eq!(x[1].bg, Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(x[1].intensity, Some(Intensity::Bold));
+
+        // colon forms, with an empty, given, and missing colour space id
+        let x = categorise_text_v3("\x1b[38:2::10:20:30ma\x1b[38:2:0:40:50:60mb\x1b[48:2:7:8:9mc");
+        assert_eq!(x[0].fg, Some(Color::Rgb(10, 20, 30)));
+        assert_eq!(x[1].fg, Some(Color::Rgb(40, 50, 60)));
+        assert_eq!(x[2].bg, Some(Color::Rgb(7, 8, 9)));
+
+        // underline styles
+        let x =
+            categorise_text_v3("\x1b[4mA\x1b[4:0mB\x1b[4:3mC\x1b[4:5mD\x1b[4:0;4:1mE\x1b[4:9mF");
+        let underlines: Vec<_> = x.iter().map(|s| s.underline).collect();
+        assert_eq!(
+            underlines,
+            [
+                Some(true),
+                Some(false),
+                Some(true),
+                Some(true),
+                Some(true),
+                Some(true)
+            ]
+        );
+
+        // a bad component consumes the colour but leaves the following parameters
+        let x = categorise_text_v3("\x1b[32;38;2;300;0;0;3mz");
+        assert_eq!(x[0].fg, Some(Color::Green));
+        assert_eq!(x[0].italic, Some(true));
+    }
+
+    #[test]
+    fn non_csi_sequences_removed() {
+        let x = categorise_text_v3(
+            "\x1b]0;title\x07\x1b[31mred\x1b]8;;https://x.org\x1b\\link\x1b]8;;\x1b\\\x1b7\x1b(0q\x1b(B\x1bPdcs\x1b\\",
+        );
+        assert_eq!(v3::construct_text_no_codes(&x), "redlink─");
+        assert!(x.iter().all(|s| s.fg == Some(Color::Red)));
+
+        // an aborted CSI does not style
+        let x = categorise_text_v3("\x1b[31\x1b[4mz");
+        assert_eq!(x[0].fg, None);
+        assert_eq!(x[0].italic, None);
+        assert_eq!(x[0].underline, Some(true));
+    }
+
+    #[test]
+    fn only_sgr_styles() {
+        let x = categorise_text_v3("\x1b[31mred\x1b[2K\x1b[10;5Hstill\x1b[1Anot bold");
+        assert_eq!(v3::construct_text_no_codes(&x), "redstillnot bold");
+        assert!(x
+            .iter()
+            .all(|s| s.fg == Some(Color::Red) && s.intensity.is_none()));
+
+        let (x, controls) =
+            categorise_text_and_controls_v3("\x1b]0;title\x07\x1b[1ma\x1b[?25lb\x1b[0m");
+        assert_eq!(v3::construct_text_no_codes(&x), "ab");
+        assert!(x.iter().all(|s| s.intensity == Some(Intensity::Bold)));
+        assert_eq!(
+            controls,
+            vec![
+                Match {
+                    start: 0,
+                    end: 10,
+                    text: "\x1b]0;title\x07"
+                },
+                Match {
+                    start: 15,
+                    end: 21,
+                    text: "\x1b[?25l"
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn bytes_match_text() {
+        let text = "👋, \x1b[31;4m🌍\x1b]0;t\x07\x1b[0m!";
+        let x = categorise_text_v3(text);
+        let y = categorise_bytes(text.as_bytes());
+        assert_eq!(x.len(), y.len());
+        for (x, y) in x.iter().zip(&y) {
+            assert_eq!(x.text.as_bytes(), y.text);
+            assert_eq!((x.start, x.end), (y.start, y.end));
+            assert_eq!(x.style(), y.style);
+        }
+
+        // translated characters and shifts
+        let text = "a\x1b(0lq\x0e\x1b)0x\x0f\x1b(Bq\x1b*0\x1bNqé\x1bNq";
+        let x = categorise_text_v3(text);
+        let y = categorise_bytes(text.as_bytes());
+        assert_eq!(x.len(), y.len());
+        for (x, y) in x.iter().zip(&y) {
+            assert_eq!(x.text.as_bytes(), y.text);
+            assert_eq!((x.start, x.end), (y.start, y.end));
+        }
+        assert_eq!(v3::construct_text_no_codes(&x), "a┌─│q─é─");
+        assert_eq!(
+            categorise_bytes_iter(text.as_bytes()).collect::<Vec<_>>(),
+            y
+        );
+        let x = categorise_bytes(b"\x1b(0lq\x0e\x1b(B");
+        let bytes: Vec<u8> = x.iter().flat_map(|s| s.text.iter().copied()).collect();
+        assert_eq!(bytes, "┌─".as_bytes());
+
+        // invalid UTF-8, including a truncated multibyte char before an escape
+        let x = categorise_bytes(b"\xf0\x9f\x1b[1m\xe9t\xe9\x1b[0m\xf0");
+        assert_eq!(x.len(), 3);
+        assert_eq!((x[0].text, x[0].start, x[0].end), (&b"\xf0\x9f"[..], 0, 2));
+        assert_eq!((x[1].text, x[1].start, x[1].end), (&b"\xe9t\xe9"[..], 6, 9));
+        assert_eq!(x[1].style.intensity, Some(Intensity::Bold));
+        assert_eq!((x[2].text, x[2].start, x[2].end), (&b"\xf0"[..], 13, 14));
+        assert_eq!(x[2].style.intensity, None);
+    }
+
+    /// Feeds `text` to a `Categoriser` in chunks of `size` bytes, returning each text byte with
+    /// its style.
+    fn feed_chunked(text: &[u8], size: usize) -> Vec<(u8, Option<Color>, Option<bool>)> {
+        let mut c = Categoriser::new();
+        let mut v = Vec::new();
+        for chunk in text.chunks(size) {
+            for s in c.feed_bytes(chunk) {
+                v.extend(s.text.iter().map(|&b| (b, s.style.fg, s.style.underline)));
+            }
+        }
+        assert_eq!(c.finish(), Vec::<u8>::new());
+        v
+    }
+
+    #[test]
+    fn categoriser_every_split() {
+        let text = "\x1b[31mred\x1b]0;title\x1b\\\x1b[4;38;2;1;2;3mline\x1b[31\x1b[24mz\x1b]2;y\x1b[39m\x1b7\x1b)0\x0eq\x0f.";
+        let whole: Vec<_> = categorise_bytes(text.as_bytes())
+            .iter()
+            .flat_map(|s| {
+                s.text
+                    .iter()
+                    .map(move |&b| (b, s.style.fg, s.style.underline))
+            })
+            .collect();
+        assert_eq!(
+            whole.iter().map(|x| x.0).collect::<Vec<_>>(),
+            "redlinez─.".as_bytes()
+        );
+
+        for size in 1..=text.len() {
+            assert_eq!(
+                feed_chunked(text.as_bytes(), size),
+                whole,
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn categoriser_str_and_finish() {
+        let mut c = Categoriser::new();
+        let x = c.feed("a\x1b]0;ti");
+        assert_eq!(x, vec![v3::CategorisedSlice::default_style("a", 0, 1)]);
+        assert_eq!(c.feed("tle"), vec![]);
+        let x = c.feed("\x07\x1b[1mb\x1b[");
+        assert_eq!(x.len(), 1);
+        assert_eq!((x[0].text, x[0].start, x[0].end), ("b", 5, 6));
+        assert_eq!(x[0].intensity, Some(Intensity::Bold));
+        assert_eq!(c.finish(), b"\x1b[");
+        // style carries on after finishing
+        assert_eq!(c.feed("c")[0].intensity, Some(Intensity::Bold));
+    }
+
+    #[test]
+    fn categoriser_abandons_long_sequences() {
+        let line = "é".repeat(50) + "\n";
+        let mut c = Categoriser::new();
+        assert_eq!(
+            c.feed("a\x1b]0;;"),
+            vec![v3::CategorisedSlice::default_style("a", 0, 1)]
+        );
+        let mut fed = 6;
+        let mut text = String::new();
+        while text.is_empty() {
+            text = c.feed(&line).iter().map(|s| s.text).collect();
+            fed += line.len();
+            assert!(c.pending.len() <= MAX_PENDING);
+        }
+        // the held bytes are dropped and the text resumes at a character boundary
+        assert_eq!(fed - text.len(), 1 + MAX_PENDING + 1);
+        assert_eq!(c.feed("\x1b[1mb")[0].intensity, Some(Intensity::Bold));
+
+        // and within a single chunk
+        let long = format!("a\x1bP{}b", "x".repeat(MAX_PENDING));
+        let x = c.feed(&long);
+        assert_eq!(x.len(), 2);
+        assert_eq!((x[1].text, x[1].start), ("xxb", MAX_PENDING + 1));
+        assert_eq!(c.finish(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn categorise_iter_matches_vec() {
+        let txt = format!(
+            "{}{}\x1b]0;t\x07{}{}",
+            "hello".bright_green(),
+            "world".red().on_blue(),
+            "whatever",
+            "!".bold()
+        );
+        let v = categorise_text_v3(&txt);
+        assert_eq!(categorise_iter(&txt).collect::<Vec<_>>(), v);
+        assert_eq!(categorise_iter("").next(), None);
+        assert_eq!(categorise_iter("\x1b[1m\x1b[0m").next(), None);
+    }
+
+    #[test]
+    fn categorise_translates_charsets() {
+        let text = "\x1b(0lqqk\x1b(B";
+        assert_eq!(construct_text_no_codes(&categorise_text(text)), "┌──┐");
+
+        let text = "x\x1b[1m\x1b(0lA\x1b(B\x1b)0a\x0eq\nq\x0fb";
+        let slices = categorise_text_v3(text);
+        assert_eq!(v3::construct_text_no_codes(&slices), "x┌Aa─\n─b");
+        let pieces: Vec<_> = slices.iter().map(|s| (s.text, s.start, s.end)).collect();
+        assert_eq!(
+            pieces,
+            [
+                ("x", 0, 1),
+                ("┌", 8, 9),
+                ("A", 9, 10),
+                ("a", 16, 17),
+                ("─", 18, 19),
+                ("\n", 19, 20),
+                ("─", 20, 21),
+                ("b", 22, 23),
+            ]
+        );
+        assert_eq!(slices[1].intensity, Some(Intensity::Bold));
+        assert_eq!(categorise_iter(text).collect::<Vec<_>>(), slices);
+
+        for size in 1..=text.len() {
+            let mut c = Categoriser::new();
+            let mut stripped = String::new();
+            for chunk in text.as_bytes().chunks(size) {
+                let chunk = core::str::from_utf8(chunk).unwrap();
+                stripped.extend(c.feed(chunk).iter().map(|s| s.text));
+            }
+            assert_eq!(stripped, "x┌Aa─\n─b", "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn categorise_with_matches_vec() {
+        let texts = [
+            "",
+            "plain",
+            "\x1b[31mred\x1b[2K\x1b[1Gover\x1b]0;t\x07\x1b[0m.",
+            "\x1b[1mbold\x1b[3",
+            "\x1b[4mline\x1b]0;unterminated",
+            "tail\x1b",
+        ];
+        for text in texts {
+            let mut slices = Vec::new();
+            let mut controls = Vec::new();
+            categorise_with(text, |s| slices.push(s), |m| controls.push(m));
+            assert_eq!(
+                (slices, controls),
+                categorise_text_and_controls_v3(text),
+                "{:?}",
+                text
+            );
+        }
+    }
 }
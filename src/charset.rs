@@ -0,0 +1,285 @@
+#[cfg(feature = "alloc")]
+use crate::categorise::{Token, Tokens};
+use crate::parsing::ESC;
+
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::string::String;
+
+/// Rewrites text drawn with the DEC Special Graphics character set as Unicode, removing the
+/// sequences which designate and invoke character sets.
+///
+/// Programs which draw boxes for terminals without Unicode switch to the line drawing set and
+/// write letters, so `lqqk` draws `┌──┐`. The sets are designated to G0 to G3 with `ESC ( F`,
+/// `ESC ) F`, `ESC * F` and `ESC + F`, and invoked with the `SO` and `SI` controls, `LS2` and
+/// `LS3` (`ESC n`, `ESC o`), or for a single character with `SS2` and `SS3` (`ESC N`, `ESC O`).
+/// Sets other than DEC Special Graphics (`0`) are treated as ASCII.
+///
+/// All other escape sequences are kept, so the decoded text can be categorised and stripped as
+/// usual, with positions in the decoded text. The text starts with ASCII invoked.
+///
+/// `categorise_text` translates the sets as well, but its slices keep the positions of the
+/// original text, so a translated character's slice is longer than its `start..end`.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let boxed = "\x1b(0lqqk\x1b(B\n\x1b[1mx\x1b[0mok\x1b[1mx\x1b[0m\n\x0e\x1b)0mqqj\x0f";
+/// let text = decode_charsets(boxed);
+/// assert_eq!(text, "┌──┐\n\x1b[1mx\x1b[0mok\x1b[1mx\x1b[0m\n└──┘");
+/// assert_eq!(construct_text_no_codes(&categorise_text(&text)), "┌──┐\nxokx\n└──┘");
+/// ```
+#[cfg(feature = "alloc")]
+pub fn decode_charsets(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut charsets = Charsets::default();
+    let mut from = 0;
+
+    for token in Tokens::new(text.as_bytes()) {
+        match token {
+            Token::Text(_, lo, hi) => {
+                // the SGR sequences before the text
+                decoded.push_str(&text[from..lo]);
+                for c in text[lo..hi].chars() {
+                    if !charsets.shift(c) {
+                        decoded.push(if c.is_control() { c } else { charsets.map(c) });
+                    }
+                }
+                from = hi;
+            }
+            Token::Control(start, end) => {
+                decoded.push_str(&text[from..start]);
+                if !charsets.control(&text.as_bytes()[start..end]) {
+                    decoded.push_str(&text[start..end]);
+                }
+                from = end;
+            }
+        }
+    }
+
+    decoded.push_str(&text[from..]);
+    decoded
+}
+
+const SO: u8 = 0x0e;
+const SI: u8 = 0x0f;
+
+/// The character sets designated to G0 to G3 and which of them are invoked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Charsets {
+    sets: [Charset; 4],
+    /// The set invoked for printing.
+    invoked: usize,
+    /// The set invoked for the next character only.
+    single: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Charset {
+    #[default]
+    Ascii,
+    DecGraphics,
+}
+
+impl Charsets {
+    /// Apply an escape sequence which designates or invokes a set, returning whether it was one.
+    pub(crate) fn control(&mut self, seq: &[u8]) -> bool {
+        match seq {
+            [ESC, g @ b'('..=b'+', designation @ ..] => {
+                self.sets[usize::from(g - b'(')] = match designation {
+                    [b'0'] => Charset::DecGraphics,
+                    _ => Charset::Ascii,
+                };
+            }
+            [ESC, b'n'] => self.invoked = 2,
+            [ESC, b'o'] => self.invoked = 3,
+            [ESC, b'N'] => self.single = Some(2),
+            [ESC, b'O'] => self.single = Some(3),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Apply a shift out (`SO`) or shift in (`SI`) control, returning whether it was one.
+    pub(crate) fn shift(&mut self, c: char) -> bool {
+        match c {
+            '\x0e' => self.invoked = 1,
+            '\x0f' => self.invoked = 0,
+            _ => return false,
+        }
+        true
+    }
+
+    /// The character a printed character is drawn as.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn map(&mut self, c: char) -> char {
+        self.translate(c)
+            .and_then(|s| s.chars().next())
+            .unwrap_or(c)
+    }
+
+    /// The text a printed character is drawn as, if it is drawn as another character.
+    fn translate(&mut self, c: char) -> Option<&'static str> {
+        match self.printed() {
+            Charset::Ascii => None,
+            Charset::DecGraphics => dec_graphic(c),
+        }
+    }
+
+    /// The set the next printed character is drawn from, using up a single shift.
+    fn printed(&mut self) -> Charset {
+        self.sets[self.single.take().unwrap_or(self.invoked)]
+    }
+}
+
+/// Splits a run of text into the pieces which are shown as they are and the characters which are
+/// drawn from another set, dropping the `SO` and `SI` controls.
+///
+/// The run is walked as bytes, so it need not be valid UTF-8. Only ASCII characters are
+/// translated or dropped, so the pieces of a `str` fall on `char` boundaries.
+#[derive(Debug, Clone)]
+pub(crate) struct Translate<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    hi: usize,
+    /// A translated character found after a piece, returned on the next call.
+    translated: Option<(&'static str, usize, usize)>,
+}
+
+impl<'a> Translate<'a> {
+    /// Translate `bytes[lo..hi]`.
+    pub(crate) fn new(bytes: &'a [u8], lo: usize, hi: usize) -> Self {
+        Self {
+            bytes,
+            pos: lo,
+            hi,
+            translated: None,
+        }
+    }
+
+    /// The next piece and its range within the original text: `None` for a piece shown as it is,
+    /// or the text a character is translated to, with the range of the original character.
+    pub(crate) fn next(
+        &mut self,
+        charsets: &mut Charsets,
+    ) -> Option<(Option<&'static str>, usize, usize)> {
+        loop {
+            if let Some((shown, lo, hi)) = self.translated.take() {
+                return Some((Some(shown), lo, hi));
+            }
+
+            let start = self.pos;
+            if start == self.hi {
+                return None;
+            }
+
+            let mut end = self.hi;
+            self.pos = self.hi;
+            for i in start..self.hi {
+                let b = self.bytes[i];
+                let shown = match b {
+                    SO | SI => {
+                        charsets.shift(char::from(b));
+                        Some("")
+                    }
+                    0x20..=0x7e => charsets.translate(char::from(b)),
+                    // C0 controls, DEL and UTF-8 continuation bytes
+                    0x00..=0xbf => None,
+                    // C1 controls
+                    0xc2 if matches!(self.bytes.get(i + 1), Some(0x80..=0x9f)) => None,
+                    _ => {
+                        // the start of a character outside the sets, which still uses a single shift
+                        charsets.printed();
+                        None
+                    }
+                };
+
+                if let Some(shown) = shown {
+                    end = i;
+                    self.pos = i + 1;
+                    if !shown.is_empty() {
+                        self.translated = Some((shown, i, i + 1));
+                    }
+                    break;
+                }
+            }
+
+            if end != start {
+                return Some((None, start, end));
+            }
+        }
+    }
+}
+
+/// The Unicode character for a character of the DEC Special Graphics set.
+fn dec_graphic(c: char) -> Option<&'static str> {
+    let s = match c {
+        '_' => "\u{a0}",
+        '`' => "◆",
+        'a' => "▒",
+        'b' => "␉",
+        'c' => "␌",
+        'd' => "␍",
+        'e' => "␊",
+        'f' => "°",
+        'g' => "±",
+        'h' => "␤",
+        'i' => "␋",
+        'j' => "┘",
+        'k' => "┐",
+        'l' => "┌",
+        'm' => "└",
+        'n' => "┼",
+        'o' => "⎺",
+        'p' => "⎻",
+        'q' => "─",
+        'r' => "⎼",
+        's' => "⎽",
+        't' => "├",
+        'u' => "┤",
+        'v' => "┴",
+        'w' => "┬",
+        'x' => "│",
+        'y' => "≤",
+        'z' => "≥",
+        '{' => "π",
+        '|' => "≠",
+        '}' => "£",
+        '~' => "·",
+        _ => return None,
+    };
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_graphics() {
+        assert_eq!(decode_charsets(""), "");
+        assert_eq!(
+            decode_charsets("plain \x1b[31mred\x1b[0m"),
+            "plain \x1b[31mred\x1b[0m"
+        );
+        assert_eq!(
+            decode_charsets("\x1b(0`abcdefghijklmnopqrstuvwxyz{|}~AZ_"),
+            "◆▒␉␌␍␊°±␤␋┘┐┌└┼⎺⎻─⎼⎽├┤┴┬│≤≥π≠£·AZ\u{a0}"
+        );
+        assert_eq!(decode_charsets("\x1b(0x\x1b(Bx\x1b(Ax"), "│xx");
+
+        // styles and other sequences are kept
+        assert_eq!(
+            decode_charsets("\x1b(0\x1b[32mq\x1b]0;tiq\x07q\x1b[0m"),
+            "\x1b[32m─\x1b]0;tiq\x07─\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn invokes_sets() {
+        assert_eq!(decode_charsets("\x1b)0q\x0eq\x0fq"), "q─q");
+        assert_eq!(decode_charsets("\x1b*0q\x1bnq\x0fq"), "q─q");
+        assert_eq!(decode_charsets("\x1b+0q\x1boq\x0fq"), "q─q");
+        assert_eq!(decode_charsets("\x1b*0q\x1bNqq\x1bN\nq"), "q─q\n─");
+        assert_eq!(decode_charsets("\x1b+0\x1bOqq"), "─q");
+    }
+}
@@ -0,0 +1,354 @@
+use super::*;
+use crate::categorise::{Token, Tokens};
+use crate::charset::{Charsets, Translate};
+use crate::parsing::{Csi, ESC};
+use crate::width::{graphemes, text_width};
+
+/// Blanks left by moving or erasing, which are not part of the original text.
+const SPACES: &str = "                                ";
+
+/// Collapse each line of the text to what a terminal would finally show of it, keeping styles.
+///
+/// Output which redraws a line in place, such as a progress bar, holds every frame of the line.
+/// This interprets the carriage returns (`\r`), backspaces (`\b`), erases (`CSI K`, `CSI 1K` and
+/// `CSI 2K`) and column moves (`CSI n G`) within each line, so only the final characters of each
+/// column are kept. Unlike a [`Screen`](crate::v3::Screen), lines are never wrapped and moves
+/// between lines are not followed, so it needs no size and is cheap enough for whole logs.
+///
+/// The slices borrow the text they were written with, so `start` and `end` are the positions of
+/// the text which survived. Columns left blank by moving or erasing are filled with unstyled
+/// spaces which are not part of the original text, so their `start` and `end` are both the
+/// position of the next slice. Trailing blanks are trimmed, a tab is kept as a single character
+/// spanning to the next multiple of 8 columns, and other escape sequences and control characters
+/// are dropped. Line endings are kept, including a `\r\n`. Characters drawn with the DEC
+/// Special Graphics set are translated, as `categorise_text` does.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let log = "\x1b[1mBuilding\x1b[0m [=>  ] 1/3\r\x1b[1mBuilding\x1b[0m [==> ] 2/3\r\x1b[K\
+///            \x1b[32mFinished\x1b[0m\n\
+///            Downloading 90%\r\x1b[13G100%\n";
+///
+/// let slices = collapse(log);
+/// assert_eq!(construct_text_no_codes(&slices), "Finished\nDownloading 100%\n");
+/// assert_eq!(slices[0].text, "Finished");
+/// assert_eq!(slices[0].fg, Some(Color::Green));
+/// assert_eq!(&log[slices[0].start..slices[0].end], "Finished");
+/// ```
+pub fn collapse(text: &str) -> v3::CategorisedSlices<'_> {
+    let mut collapsed = Collapsed {
+        text,
+        slices: Vec::new(),
+    };
+    let mut line = Line::default();
+    let mut charsets = Charsets::default();
+
+    for token in Tokens::new(text.as_bytes()) {
+        match token {
+            Token::Text(style, lo, hi) => {
+                let mut pieces = Translate::new(text.as_bytes(), lo, hi);
+                while let Some((shown, lo, hi)) = pieces.next(&mut charsets) {
+                    if let Some(shown) = shown {
+                        let piece = Piece {
+                            start: lo,
+                            end: hi,
+                            style,
+                            shown: Some(shown),
+                        };
+                        line.print(piece, text_width(shown));
+                        continue;
+                    }
+
+                    let mut start = lo;
+                    for (cluster, width) in graphemes(&text[lo..hi]) {
+                        let piece = Piece {
+                            start,
+                            end: start + cluster.len(),
+                            style,
+                            shown: None,
+                        };
+                        start = piece.end;
+
+                        match cluster {
+                            "\n" => {
+                                line.finish(&mut collapsed);
+                                collapsed.push(Piece {
+                                    // keep a `\r\n` line ending whole
+                                    start: if text[..piece.start].ends_with('\r') {
+                                        piece.start - 1
+                                    } else {
+                                        piece.start
+                                    },
+                                    ..piece
+                                });
+                            }
+                            "\r" => line.col = 0,
+                            "\x08" => line.col = line.col.saturating_sub(1),
+                            "\t" => line.print(piece, 8 - line.col % 8),
+                            _ if cluster.starts_with(char::is_control) => (),
+                            _ if width == 0 => line.mark(piece),
+                            _ => line.print(piece, width),
+                        }
+                    }
+                }
+            }
+            Token::Control(start, end) => {
+                let seq = &text.as_bytes()[start..end];
+                if let [ESC, b'[', rest @ ..] = seq {
+                    line.csi(Csi::parse(rest));
+                } else {
+                    charsets.control(seq);
+                }
+            }
+        }
+    }
+
+    line.finish(&mut collapsed);
+    collapsed.slices
+}
+
+/// The slices collapsed so far.
+struct Collapsed<'text> {
+    text: &'text str,
+    slices: v3::CategorisedSlices<'text>,
+}
+
+impl<'text> Collapsed<'text> {
+    /// Add a piece of the text, joining it to the last slice if it follows it directly.
+    fn push(&mut self, piece: Piece) {
+        if let Some(last) = self.slices.last_mut() {
+            let original = last.text.len() == last.end - last.start && piece.shown.is_none();
+            if original && last.end == piece.start && last.style() == piece.style {
+                last.end = piece.end;
+                last.text = &self.text[last.start..last.end];
+                return;
+            }
+        }
+
+        self.slices.push(v3::CategorisedSlice::with_sgr(
+            piece.style,
+            piece.shown.unwrap_or(&self.text[piece.start..piece.end]),
+            piece.start,
+            piece.end,
+        ));
+    }
+
+    /// Add `n` blank columns before the text at `pos`.
+    fn blanks(&mut self, mut n: usize, pos: usize) {
+        while n > 0 {
+            let len = n.min(SPACES.len());
+            self.slices.push(v3::CategorisedSlice::with_sgr(
+                Style::default(),
+                &SPACES[..len],
+                pos,
+                pos,
+            ));
+            n -= len;
+        }
+    }
+}
+
+/// A styled byte range of the text.
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    start: usize,
+    end: usize,
+    style: Style,
+    /// The text a translated character is shown as.
+    shown: Option<&'static str>,
+}
+
+/// A column of a line.
+#[derive(Debug)]
+enum Cell {
+    Blank,
+    /// A character and the zero width clusters written after it.
+    Glyph {
+        piece: Piece,
+        width: usize,
+        marks: Vec<Piece>,
+    },
+    /// A column covered by the wider glyph before it.
+    Cont,
+}
+
+/// The columns of the line being collapsed.
+#[derive(Debug, Default)]
+struct Line {
+    cells: Vec<Cell>,
+    col: usize,
+}
+
+impl Line {
+    fn print(&mut self, piece: Piece, width: usize) {
+        let (col, end) = (self.col, self.col + width);
+        if self.cells.len() < end {
+            self.cells.resize_with(end, || Cell::Blank);
+        }
+        for c in col..end {
+            self.blank(c);
+        }
+
+        self.cells[col] = Cell::Glyph {
+            piece,
+            width,
+            marks: Vec::new(),
+        };
+        for cell in &mut self.cells[col + 1..end] {
+            *cell = Cell::Cont;
+        }
+        self.col = end;
+    }
+
+    /// Add a zero width cluster to the glyph before the cursor. Without one it is dropped.
+    fn mark(&mut self, piece: Piece) {
+        let col = self.col.min(self.cells.len());
+        let cells = &mut self.cells[..col];
+        if let Some(Cell::Glyph { marks, .. }) = cells
+            .iter_mut()
+            .rev()
+            .find(|cell| !matches!(cell, Cell::Cont))
+        {
+            marks.push(piece);
+        }
+    }
+
+    /// Blank the column, and the rest of any wide glyph it is part of.
+    fn blank(&mut self, col: usize) {
+        let (start, width) = match self.cells.get(col) {
+            None | Some(Cell::Blank) => return,
+            Some(Cell::Glyph { width, .. }) => (col, *width),
+            Some(Cell::Cont) => match self.cells[..col]
+                .iter()
+                .rposition(|cell| !matches!(cell, Cell::Cont))
+            {
+                Some(start) => match self.cells[start] {
+                    Cell::Glyph { width, .. } => (start, width),
+                    _ => (start, 1),
+                },
+                None => (0, col + 1),
+            },
+        };
+        for cell in &mut self.cells[start..start + width] {
+            *cell = Cell::Blank;
+        }
+    }
+
+    fn csi(&mut self, csi: Csi) {
+        if csi.private != 0 || csi.intermediate != 0 {
+            return;
+        }
+        match (csi.action, csi.param(0, 0)) {
+            (b'K', 0) => {
+                self.blank(self.col);
+                self.cells.truncate(self.col);
+            }
+            (b'K', 1) => {
+                for col in 0..=self.col {
+                    self.blank(col);
+                }
+            }
+            (b'K', 2) => self.cells.clear(),
+            (b'G', n) => self.col = n.max(1) - 1,
+            _ => (),
+        }
+    }
+
+    /// Add the final columns of the line to the slices.
+    fn finish(&mut self, collapsed: &mut Collapsed) {
+        let mut blanks = 0;
+        for cell in self.cells.drain(..) {
+            match cell {
+                Cell::Blank => blanks += 1,
+                Cell::Glyph { piece, marks, .. } => {
+                    collapsed.blanks(blanks, piece.start);
+                    blanks = 0;
+                    collapsed.push(piece);
+                    for mark in marks {
+                        collapsed.push(mark);
+                    }
+                }
+                Cell::Cont => (),
+            }
+        }
+        self.col = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> String {
+        v3::construct_text_no_codes(&collapse(text))
+    }
+
+    #[test]
+    fn collapses_lines() {
+        assert_eq!(plain(""), "");
+        assert_eq!(plain("plain text\nlines"), "plain text\nlines");
+        assert_eq!(plain("10%\r20%\r100%\ndone"), "100%\ndone");
+        assert_eq!(plain("longer line\rshort"), "shortr line");
+        assert_eq!(plain("abc\x08\x08X\x08\x08\x08\x08Y"), "YXc");
+        assert_eq!(plain("one\r\ntwo\r\n"), "one\r\ntwo\r\n");
+        assert_eq!(plain("one\r\x1b[0m\ntwo"), "one\ntwo");
+        assert_eq!(plain("a\tb"), "a\tb");
+        assert_eq!(plain("a\tb\r\x1b[3Gc"), "a c     b");
+        assert_eq!(plain("bell\x07 \x1b]0;title\x07"), "bell ");
+    }
+
+    #[test]
+    fn erases_and_moves() {
+        assert_eq!(plain("longer line\rshort\x1b[K"), "short");
+        assert_eq!(plain("longer line\r\x1b[2Kshort"), "short");
+        assert_eq!(plain("longer line\x1b[7G\x1b[1K"), "       line");
+        assert_eq!(plain("abc\x1b[6Gx\x1b[Gy"), "ybc  x");
+        assert_eq!(plain("abc\x1b[2G\x1b[K\x1b[10G"), "a");
+
+        let slices = collapse("ab\x1b[5Gx");
+        assert_eq!(slices[1].text, "  ");
+        assert_eq!((slices[1].start, slices[1].end), (6, 6));
+        assert_eq!((slices[2].start, slices[2].end), (6, 7));
+    }
+
+    #[test]
+    fn keeps_styles() {
+        let text = "\x1b[31mred\x1b[0m plain\r\x1b[1mB";
+        let slices = collapse(text);
+        assert_eq!(v3::construct_text_no_codes(&slices), "Bed plain");
+        assert_eq!(slices.len(), 3);
+        assert_eq!(slices[0].intensity, Some(Intensity::Bold));
+        assert_eq!(slices[1].text, "ed");
+        assert_eq!(slices[1].fg, Some(Color::Red));
+        assert_eq!(&text[slices[1].start..slices[1].end], "ed");
+        assert_eq!(slices[2].text, " plain");
+        assert_eq!(slices[2].fg, None);
+
+        // unchanged lines are the same as categorising
+        let text = "\x1b[32mgreen\x1b[0m\n\x1b[4munder\x1b[24mlined";
+        assert_eq!(collapse(text), v3::categorise_text(text));
+    }
+
+    #[test]
+    fn wide_characters() {
+        assert_eq!(plain("中文\x1b[2Gx"), " x文");
+        assert_eq!(plain("中文\x1b[3Gxy"), "中xy");
+        assert_eq!(plain("ab\rc中"), "c中");
+        assert_eq!(plain("e\u{301}x\x1b[2G\u{302}"), "e\u{301}\u{302}x");
+        assert_eq!(plain("\u{301}a"), "a");
+    }
+
+    #[test]
+    fn translates_charsets() {
+        assert_eq!(plain("\x1b(0lqk\x1b(B"), "┌─┐");
+        assert_eq!(plain("\x1b)0x\x0eqq\x0fx\r\x1b[2Gx"), "xx─x");
+
+        let slices = collapse("ab\x1b(0qq\x1b(B\r\x1b[2Gq");
+        let pieces: Vec<_> = slices.iter().map(|s| (s.text, s.start, s.end)).collect();
+        assert_eq!(
+            pieces,
+            [("a", 0, 1), ("q", 15, 16), ("─", 5, 6), ("─", 6, 7)]
+        );
+    }
+}
@@ -0,0 +1,540 @@
+use super::*;
+#[cfg(feature = "alloc")]
+use crate::categorise::{Token, Tokens};
+use crate::downsample::ColorLevel;
+use core::fmt::{self, Display, Write};
+
+#[cfg(feature = "std")]
+use crate::detect::detect_color_level;
+
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::string::{String, ToString};
+
+/// A wrapper around categorised slices which displays the text _with_ ANSI escape codes.
+///
+/// The shortest `SGR` sequence (see [`StyleTransition`]) is written whenever the style changes
+/// between slices, and the text is finished with a reset if it ends styled. Attributes which are
+/// explicitly off (such as `italic: Some(false)`) render as the default and are not written, so
+/// categorising the output gives slices with the same text and the same [`Style::normalised`]
+/// styles.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let mut slices = categorise_text("\x1b[31mhello\x1b[0m, world");
+/// slices[1].fg = Some(Color::Indexed(208));
+/// slices[1].underline = Some(true);
+///
+/// assert_eq!(
+///     StyledText(&slices).to_string(),
+///     "\x1b[31mhello\x1b[4;38;5;208m, world\x1b[0m"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct StyledText<'a, 'text>(pub &'a [v3::CategorisedSlice<'text>]);
+
+impl Display for StyledText<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_styled(f, self.0, ColorLevel::TrueColor)
+    }
+}
+
+/// Write the slices with escape codes, with colours downsampled to `level`.
+fn write_styled<W: Write>(
+    w: &mut W,
+    slices: &[v3::CategorisedSlice],
+    level: ColorLevel,
+) -> fmt::Result {
+    let mut current = Style::default();
+
+    for slice in slices {
+        let style = slice.style().downsampled(level).normalised();
+        if style != current {
+            write!(w, "{}", current.transition(style))?;
+            current = style;
+        }
+        w.write_str(slice.text)?;
+    }
+
+    if current != Style::default() {
+        w.write_str("\x1b[0m")?;
+    }
+
+    Ok(())
+}
+
+/// Constructs a string of the categorised text _with_ ANSI escape codes, see [`StyledText`].
+///
+/// This is the inverse of `categorise_text`, slices can be categorised, transformed, and
+/// constructed back into escaped text.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let text = "\x1b[1m\x1b[31mhello\x1b[0m, \x1b[4mworld";
+/// let slices: Vec<_> = categorise_text(text)
+///     .into_iter()
+///     .filter(|s| s.text != ", ")
+///     .collect();
+/// assert_eq!(
+///     &construct_text_with_codes(&slices),
+///     "\x1b[1;31mhello\x1b[0;4mworld\x1b[0m"
+/// );
+/// ```
+#[cfg(feature = "alloc")]
+pub fn construct_text_with_codes(categorised_slices: &[v3::CategorisedSlice]) -> String {
+    StyledText(categorised_slices).to_string()
+}
+
+/// Constructs a string of the categorised text with escape codes that a terminal supporting
+/// `level` can show.
+///
+/// Colours are downsampled to the level (see [`Color::downsampled`]), other styling is kept. A
+/// [`ColorLevel::Monochrome`] terminal gets no colours at all.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[1;38;2;0;0;238mblue\x1b[0m");
+/// assert_eq!(
+///     &construct_text_for_level(&slices, ColorLevel::Ansi16),
+///     "\x1b[1;34mblue\x1b[0m"
+/// );
+/// assert_eq!(
+///     &construct_text_for_level(&slices, ColorLevel::Monochrome),
+///     "\x1b[1mblue\x1b[0m"
+/// );
+/// ```
+#[cfg(feature = "alloc")]
+pub fn construct_text_for_level(
+    categorised_slices: &[v3::CategorisedSlice],
+    level: ColorLevel,
+) -> String {
+    let mut s = String::new();
+    // writing to a String does not fail
+    let _ = write_styled(&mut s, categorised_slices, level);
+    s
+}
+
+/// Constructs a string of the categorised text with escape codes that the terminal of this
+/// process can show, see [`construct_text_for_level`] and [`detect_color_level`].
+#[cfg(feature = "std")]
+pub fn construct_text_for_terminal(categorised_slices: &[v3::CategorisedSlice]) -> String {
+    construct_text_for_level(categorised_slices, detect_color_level())
+}
+
+/// Rewrites ANSI text into a canonical form, where each change in style is made with the
+/// shortest `SGR` sequence (see [`StyleTransition`]).
+///
+/// Redundant and repeated styling sequences are removed, and the text ends with a reset if it
+/// ends styled. Escape sequences which are not styling are kept in place, after the style in
+/// effect where they appear, so an erase is filled with the same background colour.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// assert_eq!(
+///     &normalise("\x1b[0m\x1b[1m\x1b[31mred\x1b[0m\x1b[31m only\x1b[39m"),
+///     "\x1b[1;31mred\x1b[22m only\x1b[0m"
+/// );
+/// ```
+#[cfg(feature = "alloc")]
+pub fn normalise(text: &str) -> String {
+    let mut normalised = String::with_capacity(text.len());
+    // the style written so far
+    let mut current = Style::default();
+    let mut tokens = Tokens::new(text.as_bytes());
+
+    while let Some(token) = tokens.next() {
+        // controls are written in the style in effect where they appear, as erases use it
+        let (style, s) = match token {
+            Token::Text(sgr, lo, hi) => (sgr, &text[lo..hi]),
+            Token::Control(start, end) => (tokens.sgr, &text[start..end]),
+        };
+        let style = style.normalised();
+        if style != current {
+            // writing to a String does not fail
+            let _ = write!(normalised, "{}", current.transition(style));
+            current = style;
+        }
+        normalised.push_str(s);
+    }
+
+    if current != Style::default() {
+        normalised.push_str("\x1b[0m");
+    }
+
+    normalised
+}
+
+/// The shortest `SGR` sequence which changes the style `from` one style `to` another, see
+/// [`Style::transition`].
+///
+/// Displays as nothing if the styles render the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StyleTransition {
+    /// The style before the sequence.
+    pub from: Style,
+    /// The style after the sequence.
+    pub to: Style,
+}
+
+impl Style {
+    /// The shortest `SGR` sequence to move from this style to `to`.
+    ///
+    /// Either only the attributes which change are written (such as `22` to drop bold), or a
+    /// reset followed by the attributes of `to`, whichever is shorter.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use cansi::v3::*;
+    /// let slices = categorise_text("\x1b[1;31;4mone\x1b[22mtwo\x1b[0;32mthree");
+    /// let (one, two, three) = (slices[0].style(), slices[1].style(), slices[2].style());
+    ///
+    /// assert_eq!(one.transition(two).to_string(), "\x1b[22m");
+    /// assert_eq!(two.transition(three).to_string(), "\x1b[0;32m");
+    /// assert_eq!(three.transition(three).to_string(), "");
+    /// ```
+    pub fn transition(self, to: Style) -> StyleTransition {
+        StyleTransition { from: self, to }
+    }
+}
+
+impl Display for StyleTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = self.from.normalised();
+        let to = self.to.normalised();
+
+        if from == to {
+            return Ok(());
+        }
+
+        let mut diff = Counter(0);
+        write_diff(&mut Params::new(&mut diff), from, to)?;
+        let mut reset = Counter(0);
+        write_reset(&mut Params::new(&mut reset), from, to)?;
+
+        let mut params = Params::new(f);
+        if diff.0 <= reset.0 {
+            write_diff(&mut params, from, to)?;
+        } else {
+            write_reset(&mut params, from, to)?;
+        }
+        params.finish()
+    }
+}
+
+/// Write the parameters which reset, then set `to` from the defaults.
+/// A reset is unnecessary if `from` is the defaults.
+fn write_reset<W: Write>(params: &mut Params<W>, from: Style, to: Style) -> fmt::Result {
+    if from != Style::default() {
+        params.push(0)?;
+    }
+    write_params(params, to)
+}
+
+/// Write the parameters which change only the attributes that differ between the styles.
+/// Both styles are expected to be normalised.
+fn write_diff<W: Write>(params: &mut Params<W>, from: Style, to: Style) -> fmt::Result {
+    if from.intensity != to.intensity {
+        // 22 is the only way to turn off bold and faint, and terminals may show both together
+        if from.intensity.is_some() {
+            params.push(22)?;
+        }
+        match to.intensity {
+            Some(Intensity::Bold) => params.push(1)?,
+            Some(Intensity::Faint) => params.push(2)?,
+            Some(Intensity::Normal) | None => (),
+        }
+    }
+
+    let flags = [
+        (from.italic, to.italic, 3),
+        (from.underline, to.underline, 4),
+        (from.blink, to.blink, 5),
+        (from.reversed, to.reversed, 7),
+        (from.hidden, to.hidden, 8),
+        (from.strikethrough, to.strikethrough, 9),
+    ];
+    for (from, to, code) in flags {
+        if from != to {
+            // off codes are 20 more than the on codes
+            params.push(if to == Some(true) { code } else { code + 20 })?;
+        }
+    }
+
+    if from.fg != to.fg {
+        match to.fg {
+            Some(fg) => write_colour(params, fg, 30)?,
+            None => params.push(39)?,
+        }
+    }
+    if from.bg != to.bg {
+        match to.bg {
+            Some(bg) => write_colour(params, bg, 40)?,
+            None => params.push(49)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the parameters which set `style` from the defaults.
+fn write_params<W: Write>(params: &mut Params<W>, style: Style) -> fmt::Result {
+    let Style {
+        fg,
+        bg,
+        intensity,
+        italic,
+        underline,
+        blink,
+        reversed,
+        hidden,
+        strikethrough,
+    } = style;
+
+    match intensity {
+        Some(Intensity::Bold) => params.push(1)?,
+        Some(Intensity::Faint) => params.push(2)?,
+        Some(Intensity::Normal) | None => (),
+    }
+
+    let flags = [
+        (italic, 3),
+        (underline, 4),
+        (blink, 5),
+        (reversed, 7),
+        (hidden, 8),
+        (strikethrough, 9),
+    ];
+    for (flag, code) in flags {
+        if flag == Some(true) {
+            params.push(code)?;
+        }
+    }
+
+    if let Some(fg) = fg {
+        write_colour(params, fg, 30)?;
+    }
+    if let Some(bg) = bg {
+        write_colour(params, bg, 40)?;
+    }
+
+    Ok(())
+}
+
+/// Write the parameters of a colour, `base` is `30` for foreground and `40` for background.
+fn write_colour<W: Write>(params: &mut Params<W>, colour: Color, base: u8) -> fmt::Result {
+    use Color::*;
+
+    let code = |x: u8| x + base;
+    let bright = |x: u8| x + base + 60;
+
+    match colour {
+        Black => params.push(code(0)),
+        Red => params.push(code(1)),
+        Green => params.push(code(2)),
+        Yellow => params.push(code(3)),
+        Blue => params.push(code(4)),
+        Magenta => params.push(code(5)),
+        Cyan => params.push(code(6)),
+        White => params.push(code(7)),
+        BrightBlack => params.push(bright(0)),
+        BrightRed => params.push(bright(1)),
+        BrightGreen => params.push(bright(2)),
+        BrightYellow => params.push(bright(3)),
+        BrightBlue => params.push(bright(4)),
+        BrightMagenta => params.push(bright(5)),
+        BrightCyan => params.push(bright(6)),
+        BrightWhite => params.push(bright(7)),
+        Indexed(n) => {
+            params.push(code(8))?;
+            params.push(5)?;
+            params.push(n)
+        }
+        Rgb(r, g, b) => {
+            params.push(code(8))?;
+            params.push(2)?;
+            params.push(r)?;
+            params.push(g)?;
+            params.push(b)
+        }
+    }
+}
+
+/// Counts the bytes written.
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// Writes `;` separated parameters of a single `SGR` sequence.
+/// Nothing is written if no parameters are pushed.
+struct Params<'a, W> {
+    w: &'a mut W,
+    empty: bool,
+}
+
+impl<'a, W: Write> Params<'a, W> {
+    fn new(w: &'a mut W) -> Self {
+        Self { w, empty: true }
+    }
+
+    fn push(&mut self, param: u8) -> fmt::Result {
+        if self.empty {
+            self.w.write_str("\x1b[")?;
+            self.empty = false;
+        } else {
+            self.w.write_char(';')?;
+        }
+        write!(self.w, "{}", param)
+    }
+
+    fn finish(self) -> fmt::Result {
+        if self.empty {
+            Ok(())
+        } else {
+            self.w.write_char('m')
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use colored::Colorize;
+
+    /// Categorise, re-emit, and categorise again, checking the text and styles survive.
+    fn round_trip(text: &str) -> String {
+        let slices = v3::categorise_text(text);
+        let emitted = construct_text_with_codes(&slices);
+        let again = v3::categorise_text(&emitted);
+
+        assert_eq!(
+            v3::construct_text_no_codes(&slices),
+            v3::construct_text_no_codes(&again)
+        );
+        let styles = |x: &[v3::CategorisedSlice]| -> Vec<_> {
+            x.iter()
+                .flat_map(|s| s.text.chars().map(move |_| s.style().normalised()))
+                .collect()
+        };
+        assert_eq!(styles(&slices), styles(&again));
+
+        emitted
+    }
+
+    #[test]
+    fn round_trips() {
+        colored::control::set_override(true);
+
+        assert_eq!(round_trip("plain"), "plain");
+        assert_eq!(round_trip(""), "");
+        assert_eq!(
+            round_trip("\x1b[1m\x1b[31mhi\x1b[0m"),
+            "\x1b[1;31mhi\x1b[0m"
+        );
+        assert_eq!(
+            round_trip("\x1b[1;3;4;5;7;8;9;97;101mall"),
+            "\x1b[1;3;4;5;7;8;9;97;101mall\x1b[0m"
+        );
+        assert_eq!(
+            round_trip("\x1b[38;2;1;2;3;48;5;236mx\x1b[2;39my"),
+            "\x1b[38;2;1;2;3;48;5;236mx\x1b[2;39my\x1b[0m"
+        );
+        // explicitly off attributes are not written
+        assert_eq!(round_trip("\x1b[1;22;23mx"), "x");
+
+        round_trip(&format!(
+            "Hello, {}{}{}{}{}{}",
+            "w".white().on_red(),
+            "o".cyan().on_green(),
+            "r".magenta().on_yellow(),
+            "l".blue().on_white().italic(),
+            "d".yellow().on_bright_cyan().underline(),
+            "!".bright_red().on_bright_yellow().dimmed(),
+        ));
+    }
+
+    #[test]
+    fn merges_same_styles() {
+        let slices = v3::categorise_text("\x1b[31ma\x1b[31mb\x1b]0;t\x07c\x1b[0;22md");
+        assert_eq!(slices.len(), 4);
+        assert_eq!(StyledText(&slices).to_string(), "\x1b[31mabc\x1b[0md");
+    }
+
+    fn transition(from: &str, to: &str) -> String {
+        let style = |x: &str| v3::categorise_text(&format!("{}x", x))[0].style();
+        style(from).transition(style(to)).to_string()
+    }
+
+    #[test]
+    fn minimal_transitions() {
+        assert_eq!(transition("\x1b[1;31;4m", "\x1b[31;4m"), "\x1b[22m");
+        assert_eq!(transition("\x1b[1;31;4m", "\x1b[1;31;4m"), "");
+        assert_eq!(transition("\x1b[1;23m", "\x1b[1m"), "");
+        assert_eq!(transition("", "\x1b[3m"), "\x1b[3m");
+        assert_eq!(transition("\x1b[1;31m", "\x1b[2;31m"), "\x1b[22;2m");
+        assert_eq!(transition("\x1b[3;4;5;7;8;9m", ""), "\x1b[0m");
+        assert_eq!(
+            transition("\x1b[3;4;5;7;8;9m", "\x1b[3;4;5;7;8m"),
+            "\x1b[29m"
+        );
+        assert_eq!(transition("\x1b[31;42m", "\x1b[32;42m"), "\x1b[32m");
+        assert_eq!(transition("\x1b[31;42m", "\x1b[42m"), "\x1b[39m");
+        assert_eq!(
+            transition("\x1b[38;5;1m", "\x1b[38;2;1;2;3m"),
+            "\x1b[38;2;1;2;3m"
+        );
+        // resetting is shorter than turning everything off
+        assert_eq!(transition("\x1b[1;3;4;31m", "\x1b[32m"), "\x1b[0;32m");
+    }
+
+    #[test]
+    fn normalise_text() {
+        colored::control::set_override(true);
+
+        assert_eq!(normalise("plain"), "plain");
+        assert_eq!(normalise("\x1b[0m\x1b[m"), "");
+        assert_eq!(
+            normalise("\x1b[1m\x1b[1mbold\x1b[22m\x1b[1m still\x1b[0m"),
+            "\x1b[1mbold still\x1b[0m"
+        );
+        // controls are kept in place
+        assert_eq!(
+            normalise("\x1b]0;t\x07\x1b[31mred\x1b[2K\x1b[0;31m red"),
+            "\x1b]0;t\x07\x1b[31mred\x1b[2K red\x1b[0m"
+        );
+        // a reset before a control is written before it
+        assert_eq!(
+            normalise("\x1b[41mred\x1b[0m\x1b[K\x1b[41m\x1b[0mplain"),
+            "\x1b[41mred\x1b[0m\x1b[Kplain"
+        );
+
+        let s = format!("{}{}{}", "a".red().bold(), "b".red(), "c".green().italic());
+        let n = normalise(&s);
+        assert_eq!(n, "\x1b[1;31ma\x1b[22mb\x1b[3;32mc\x1b[0m");
+        assert_eq!(normalise(&n), n);
+    }
+
+    #[test]
+    fn construct_for_levels() {
+        let slices = v3::categorise_text("\x1b[3;38;5;196mred\x1b[23;48;2;1;1;1mbg\x1b[0m.");
+        assert_eq!(
+            construct_text_for_level(&slices, ColorLevel::TrueColor),
+            construct_text_with_codes(&slices)
+        );
+        assert_eq!(
+            construct_text_for_level(&slices, ColorLevel::Ansi8),
+            "\x1b[3;31mred\x1b[23;40mbg\x1b[0m."
+        );
+        assert_eq!(
+            construct_text_for_level(&slices, ColorLevel::Monochrome),
+            "\x1b[3mred\x1b[0mbg."
+        );
+    }
+}
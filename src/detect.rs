@@ -0,0 +1,178 @@
+use crate::downsample::ColorLevel;
+
+/// The colour level a terminal supports, detected from the environment of this process.
+///
+/// See [`color_level_from_env`] for how the variables are read. The environment does not say
+/// whether output goes to a terminal at all, so callers writing to a pipe or file will usually
+/// want to check that first.
+pub fn detect_color_level() -> ColorLevel {
+    color_level_from_env(
+        std::env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?))),
+    )
+}
+
+/// The colour level a terminal supports, detected from the environment variables `vars`.
+///
+/// In order:
+/// 1. A non-empty `NO_COLOR` disables colour.
+/// 2. `CLICOLOR_FORCE` (other than `0`) forces at least 16 colours, even if the rest of the
+///    environment detects none.
+/// 3. `CLICOLOR=0` disables colour.
+/// 4. `COLORTERM=truecolor` or `24bit` gives 24-bit colour, and any other `COLORTERM` at least 16
+///    colours.
+/// 5. `TERM_PROGRAM` recognises terminals which do not advertise their colours through `TERM`.
+/// 6. `TERM` gives 24-bit colour for `*-direct`, 256 colours for `*-256color`, no colour for
+///    `dumb` (or when missing), and 16 colours for other terminals.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let level = color_level_from_env([("TERM", "xterm-256color")]);
+/// assert_eq!(level, ColorLevel::Ansi256);
+///
+/// let level = color_level_from_env([("TERM", "xterm-256color"), ("NO_COLOR", "1")]);
+/// assert_eq!(level, ColorLevel::Monochrome);
+///
+/// let env: std::collections::HashMap<_, _> = [("COLORTERM", "truecolor")].into();
+/// assert_eq!(color_level_from_env(&env), ColorLevel::TrueColor);
+/// ```
+pub fn color_level_from_env<I, K, V>(vars: I) -> ColorLevel
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut env = Env::default();
+    for (k, v) in vars {
+        let v = Some(String::from(v.as_ref()));
+        match k.as_ref() {
+            "NO_COLOR" => env.no_color = v,
+            "CLICOLOR" => env.clicolor = v,
+            "CLICOLOR_FORCE" => env.clicolor_force = v,
+            "COLORTERM" => env.colorterm = v,
+            "TERM" => env.term = v,
+            "TERM_PROGRAM" => env.term_program = v,
+            _ => (),
+        }
+    }
+    env.level()
+}
+
+/// The environment variables which decide the colour level.
+#[derive(Default)]
+struct Env {
+    no_color: Option<String>,
+    clicolor: Option<String>,
+    clicolor_force: Option<String>,
+    colorterm: Option<String>,
+    term: Option<String>,
+    term_program: Option<String>,
+}
+
+impl Env {
+    fn level(&self) -> ColorLevel {
+        if self.no_color.as_deref().is_some_and(|v| !v.is_empty()) {
+            return ColorLevel::Monochrome;
+        }
+
+        let forced = self
+            .clicolor_force
+            .as_deref()
+            .is_some_and(|v| !v.is_empty() && v != "0");
+        if forced {
+            self.detected().max(ColorLevel::Ansi16)
+        } else if self.clicolor.as_deref() == Some("0") {
+            ColorLevel::Monochrome
+        } else {
+            self.detected()
+        }
+    }
+
+    /// The level the terminal itself advertises.
+    fn detected(&self) -> ColorLevel {
+        match self.colorterm.as_deref() {
+            Some("truecolor") | Some("24bit") => return ColorLevel::TrueColor,
+            Some(_) => return self.term_level().max(ColorLevel::Ansi16),
+            None => (),
+        }
+
+        match self.term_program.as_deref() {
+            Some("iTerm.app") | Some("vscode") | Some("WezTerm") | Some("Hyper") => {
+                ColorLevel::TrueColor
+            }
+            Some("Apple_Terminal") => ColorLevel::Ansi256,
+            _ => self.term_level(),
+        }
+    }
+
+    fn term_level(&self) -> ColorLevel {
+        match self.term.as_deref() {
+            None | Some("") | Some("dumb") => ColorLevel::Monochrome,
+            Some(term) if term.ends_with("-direct") || term.contains("truecolor") => {
+                ColorLevel::TrueColor
+            }
+            Some(term) if term.ends_with("256color") || term.ends_with("-256") => {
+                ColorLevel::Ansi256
+            }
+            Some(_) => ColorLevel::Ansi16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ColorLevel::*;
+
+    #[test]
+    fn levels_from_env() {
+        let level = |vars: &[(&str, &str)]| color_level_from_env(vars.iter().copied());
+
+        assert_eq!(level(&[]), Monochrome);
+        assert_eq!(level(&[("TERM", "dumb")]), Monochrome);
+        assert_eq!(level(&[("TERM", "xterm")]), Ansi16);
+        assert_eq!(level(&[("TERM", "linux")]), Ansi16);
+        assert_eq!(level(&[("TERM", "screen-256color")]), Ansi256);
+        assert_eq!(level(&[("TERM", "xterm-direct")]), TrueColor);
+        assert_eq!(level(&[("COLORTERM", "24bit")]), TrueColor);
+        assert_eq!(level(&[("COLORTERM", "yes"), ("TERM", "dumb")]), Ansi16);
+        assert_eq!(
+            level(&[("COLORTERM", "yes"), ("TERM", "xterm-256color")]),
+            Ansi256
+        );
+        assert_eq!(
+            level(&[("TERM_PROGRAM", "iTerm.app"), ("TERM", "xterm")]),
+            TrueColor
+        );
+        assert_eq!(
+            level(&[("TERM_PROGRAM", "Apple_Terminal"), ("TERM", "xterm")]),
+            Ansi256
+        );
+        assert_eq!(
+            level(&[("TERM_PROGRAM", "other"), ("TERM", "xterm")]),
+            Ansi16
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let level = |vars: &[(&str, &str)]| color_level_from_env(vars.iter().copied());
+        let term = ("COLORTERM", "truecolor");
+
+        assert_eq!(level(&[term, ("NO_COLOR", "1")]), Monochrome);
+        assert_eq!(level(&[term, ("NO_COLOR", "")]), TrueColor);
+        assert_eq!(
+            level(&[term, ("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")]),
+            Monochrome
+        );
+        assert_eq!(level(&[term, ("CLICOLOR", "0")]), Monochrome);
+        assert_eq!(level(&[term, ("CLICOLOR", "1")]), TrueColor);
+        assert_eq!(level(&[("CLICOLOR_FORCE", "1")]), Ansi16);
+        assert_eq!(level(&[("CLICOLOR_FORCE", "0")]), Monochrome);
+        assert_eq!(
+            level(&[term, ("CLICOLOR", "0"), ("CLICOLOR_FORCE", "1")]),
+            TrueColor
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use super::*;
+use crate::palette::{standard_colour, standard_index, Palette};
+
+/// How many colours a terminal can show, from none to 24-bit colour.
+///
+/// Levels are ordered, so `level >= ColorLevel::Ansi256` tests for at least 256 colours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ColorLevel {
+    /// No colours. Other styling, such as bold and underline, is kept, as `NO_COLOR` asks; use
+    /// `v3::construct_text_no_codes` for a terminal which understands no escape codes at all.
+    Monochrome,
+    /// The 8 standard colours.
+    Ansi8,
+    /// The 8 standard colours and their bright variants.
+    Ansi16,
+    /// The 256 colour palette.
+    Ansi256,
+    /// 24-bit direct colours.
+    TrueColor,
+}
+
+impl Color {
+    /// The nearest colour which a terminal at `level` can show, or `None` for
+    /// [`ColorLevel::Monochrome`].
+    ///
+    /// Colours are matched by their [`Palette::XTERM`] values with a perceptual distance, which
+    /// weights the channels by how sensitive the eye is to them. Downsampling to 256 colours only
+    /// matches the colour cube and grayscale ramp, as the first 16 entries vary between terminals.
+    /// Downsampling to 8 colours maps the bright variants to their standard colour.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use cansi::v3::*;
+    /// let orange = Color::Rgb(0xff, 0x88, 0x00);
+    /// assert_eq!(orange.downsampled(ColorLevel::TrueColor), Some(orange));
+    /// assert_eq!(orange.downsampled(ColorLevel::Ansi256), Some(Color::Indexed(208)));
+    /// assert_eq!(orange.downsampled(ColorLevel::Ansi16), Some(Color::Yellow));
+    /// assert_eq!(orange.downsampled(ColorLevel::Ansi8), Some(Color::Yellow));
+    /// assert_eq!(orange.downsampled(ColorLevel::Monochrome), None);
+    /// ```
+    pub fn downsampled(self, level: ColorLevel) -> Option<Color> {
+        let rgb = Palette::XTERM.rgb(self);
+        let colour = match (level, self) {
+            (ColorLevel::Monochrome, _) => return None,
+            (ColorLevel::TrueColor, _) => self,
+            (ColorLevel::Ansi256, Color::Rgb(..)) => Color::Indexed(nearest(rgb, 16..=255)),
+            (ColorLevel::Ansi256, _) => self,
+            (ColorLevel::Ansi16, Color::Indexed(i @ 0..=15)) => standard_colour(i as usize),
+            (ColorLevel::Ansi16, Color::Indexed(_)) | (ColorLevel::Ansi16, Color::Rgb(..)) => {
+                standard_colour(nearest(rgb, 0..=15) as usize)
+            }
+            (ColorLevel::Ansi16, _) => self,
+            (ColorLevel::Ansi8, Color::Indexed(i @ 0..=15)) => standard_colour(i as usize % 8),
+            (ColorLevel::Ansi8, Color::Indexed(_)) | (ColorLevel::Ansi8, Color::Rgb(..)) => {
+                standard_colour(nearest(rgb, 0..=7) as usize)
+            }
+            (ColorLevel::Ansi8, _) => standard_colour(standard_index(self) % 8),
+        };
+        Some(colour)
+    }
+}
+
+impl Style {
+    /// The style with its colours downsampled to `level`, see [`Color::downsampled`].
+    pub fn downsampled(self, level: ColorLevel) -> Style {
+        Style {
+            fg: self.fg.and_then(|c| c.downsampled(level)),
+            bg: self.bg.and_then(|c| c.downsampled(level)),
+            ..self
+        }
+    }
+}
+
+/// Downsample the colours of each slice to `level`, so that text constructed from the slices is
+/// safe for a terminal which supports that level.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let mut slices = categorise_text("\x1b[38;2;255;0;0;48;5;236mred\x1b[0m");
+/// downsample(&mut slices, ColorLevel::Ansi16);
+/// assert_eq!(
+///     &construct_text_with_codes(&slices),
+///     "\x1b[91;40mred\x1b[0m"
+/// );
+/// ```
+pub fn downsample(slices: &mut [v3::CategorisedSlice], level: ColorLevel) {
+    for slice in slices {
+        slice.fg = slice.fg.and_then(|c| c.downsampled(level));
+        slice.bg = slice.bg.and_then(|c| c.downsampled(level));
+    }
+}
+
+/// The index of the 256 colour palette entry within `candidates` nearest to `rgb`.
+fn nearest(rgb: (u8, u8, u8), candidates: core::ops::RangeInclusive<u8>) -> u8 {
+    candidates
+        .min_by_key(|&i| distance(rgb, Palette::XTERM.rgb(Color::Indexed(i))))
+        .unwrap_or(0)
+}
+
+/// The "redmean" approximation of perceptual distance, squared and scaled by 256.
+///
+/// Red and blue are weighted by how red the colours are, which tracks the eye's sensitivity
+/// closely without needing a conversion to a perceptual colour space.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let rmean = (a.0 as i32 + b.0 as i32) / 2;
+    let r = a.0 as i32 - b.0 as i32;
+    let g = a.1 as i32 - b.1 as i32;
+    let b = a.2 as i32 - b.2 as i32;
+    (((512 + rmean) * r * r) + 1024 * g * g + ((767 - rmean) * b * b)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Color::*;
+
+    #[test]
+    fn downsamples_colours() {
+        use ColorLevel::*;
+
+        assert_eq!(Rgb(0, 0, 0).downsampled(Ansi256), Some(Indexed(16)));
+        assert_eq!(
+            Rgb(0x80, 0x80, 0x80).downsampled(Ansi256),
+            Some(Indexed(244))
+        );
+        assert_eq!(
+            Rgb(0xd0, 0x10, 0x10).downsampled(Ansi256),
+            Some(Indexed(160))
+        );
+        assert_eq!(Indexed(208).downsampled(Ansi256), Some(Indexed(208)));
+        assert_eq!(BrightRed.downsampled(Ansi256), Some(BrightRed));
+
+        assert_eq!(Indexed(9).downsampled(Ansi16), Some(BrightRed));
+        assert_eq!(Indexed(21).downsampled(Ansi16), Some(Blue));
+        assert_eq!(Indexed(250).downsampled(Ansi16), Some(White));
+        assert_eq!(Rgb(0x10, 0x10, 0x10).downsampled(Ansi16), Some(Black));
+        assert_eq!(BrightCyan.downsampled(Ansi16), Some(BrightCyan));
+
+        assert_eq!(BrightCyan.downsampled(Ansi8), Some(Cyan));
+        assert_eq!(Indexed(12).downsampled(Ansi8), Some(Blue));
+        assert_eq!(Rgb(0xff, 0xff, 0xff).downsampled(Ansi8), Some(White));
+        assert_eq!(Rgb(0, 0x90, 0).downsampled(Ansi8), Some(Green));
+
+        for c in [Red, Indexed(100), Rgb(1, 2, 3)] {
+            assert_eq!(c.downsampled(TrueColor), Some(c));
+            assert_eq!(c.downsampled(Monochrome), None);
+        }
+    }
+
+    #[test]
+    fn downsamples_slices() {
+        let mut slices = v3::categorise_text("\x1b[1;38;5;196;48;2;0;0;200mx\x1b[0;7my");
+        downsample(&mut slices, ColorLevel::Monochrome);
+        assert_eq!(
+            &v3::construct_text_with_codes(&slices),
+            "\x1b[1mx\x1b[0;7my\x1b[0m"
+        );
+
+        let style = slices[0].style();
+        assert_eq!(style.downsampled(ColorLevel::Ansi8), style);
+    }
+}
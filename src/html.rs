@@ -0,0 +1,440 @@
+use super::*;
+use crate::palette::{standard_index, Hex, Palette};
+use core::fmt::{self, Display, Write};
+
+/// The class names of the standard and bright colours, in palette order.
+const NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright-black",
+    "bright-red",
+    "bright-green",
+    "bright-yellow",
+    "bright-blue",
+    "bright-magenta",
+    "bright-cyan",
+    "bright-white",
+];
+
+/// A wrapper around categorised slices which displays the text as HTML.
+///
+/// Styled slices are written as `<span>` runs and unstyled slices as plain text, with `&`, `<`,
+/// `>`, `"` and `'` escaped. New lines are kept, so the output is best placed in a `<pre>`
+/// element.
+///
+/// Styling is either written inline with `style=` attributes ([`Html::inline`]), or as classes
+/// such as `class="ansi-fg-red ansi-bold"` ([`Html::classes`]) which are defined by the
+/// [`Stylesheet`]. Reversed text swaps the foreground and background, hidden text is transparent,
+/// and blinking text uses the `ansi-blink` animation of the stylesheet.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[1;31mred\x1b[0m & plain");
+///
+/// assert_eq!(
+///     Html::inline(&slices).to_string(),
+///     "<span style=\"color:#cd0000;font-weight:bold;\">red</span> &amp; plain"
+/// );
+/// assert_eq!(
+///     Html::classes(&slices).to_string(),
+///     "<span class=\"ansi-fg-red ansi-bold\">red</span> &amp; plain"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Html<'a, 'text> {
+    slices: &'a [v3::CategorisedSlice<'text>],
+    classes: bool,
+    palette: Palette,
+}
+
+impl<'a, 'text> Html<'a, 'text> {
+    /// Write the styling of each run as an inline `style=` attribute.
+    pub fn inline(slices: &'a [v3::CategorisedSlice<'text>]) -> Self {
+        Html {
+            slices,
+            classes: false,
+            palette: Palette::XTERM,
+        }
+    }
+
+    /// Write the styling of each run as classes, defined by the [`Stylesheet`].
+    ///
+    /// 24-bit colours have no class and are still written inline.
+    pub fn classes(slices: &'a [v3::CategorisedSlice<'text>]) -> Self {
+        Html {
+            slices,
+            classes: true,
+            palette: Palette::XTERM,
+        }
+    }
+
+    /// Use the `palette` colours for inline styling, [`Palette::XTERM`] by default.
+    ///
+    /// Class-based output takes its colours from the [`Stylesheet`] instead.
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+}
+
+impl Display for Html<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for slice in self.slices.iter().filter(|s| !s.text.is_empty()) {
+            let style = slice.style().normalised();
+            if style == Style::default() {
+                write_escaped(f, slice.text)?;
+                continue;
+            }
+
+            f.write_str("<span")?;
+            if self.classes {
+                write_classes(f, style)?;
+            } else {
+                f.write_str(" style=\"")?;
+                write_declarations(f, style, &self.palette)?;
+                f.write_char('"')?;
+            }
+            f.write_char('>')?;
+            write_escaped(f, slice.text)?;
+            f.write_str("</span>")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The CSS which defines the classes written by [`Html::classes`].
+///
+/// Colours come from the palette, [`Palette::XTERM`] by default.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let css = Stylesheet::default().to_string();
+/// assert!(css.contains(".ansi-fg-red { color: #cd0000; }"));
+/// assert!(css.contains(".ansi-bg-208 { background-color: #ff8700; }"));
+///
+/// let css = Stylesheet(Palette::TANGO).to_string();
+/// assert!(css.contains(".ansi-fg-red { color: #cc0000; }"));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stylesheet(pub Palette);
+
+impl Display for Stylesheet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            ".ansi-bold { font-weight: bold; }\n\
+             .ansi-faint { opacity: 0.5; }\n\
+             .ansi-italic { font-style: italic; }\n\
+             .ansi-underline { text-decoration-line: underline; }\n\
+             .ansi-strikethrough { text-decoration-line: line-through; }\n\
+             .ansi-underline.ansi-strikethrough { text-decoration-line: underline line-through; }\n\
+             .ansi-blink { animation: ansi-blink 1s steps(1) infinite; }\n\
+             @keyframes ansi-blink { 50% { visibility: hidden; } }\n",
+        )?;
+
+        for (i, name) in NAMES.iter().enumerate() {
+            let colour = Hex(self.0.colours[i]);
+            writeln!(f, ".ansi-fg-{} {{ color: {}; }}", name, colour)?;
+            writeln!(f, ".ansi-bg-{} {{ background-color: {}; }}", name, colour)?;
+        }
+        for i in 0..=255 {
+            let colour = Hex(self.0.rgb(Color::Indexed(i)));
+            writeln!(f, ".ansi-fg-{} {{ color: {}; }}", i, colour)?;
+            writeln!(f, ".ansi-bg-{} {{ background-color: {}; }}", i, colour)?;
+        }
+
+        // reversed text with default colours, and hidden text, must override the colours above
+        writeln!(
+            f,
+            ".ansi-fg-reversed {{ color: {}; }}",
+            Hex(self.0.background)
+        )?;
+        writeln!(
+            f,
+            ".ansi-bg-reversed {{ background-color: {}; }}",
+            Hex(self.0.foreground)
+        )?;
+        f.write_str(".ansi-hidden { color: transparent; }\n")
+    }
+}
+
+/// A colour to paint the text or background with.
+#[derive(Clone, Copy)]
+enum Paint {
+    Colour(Color),
+    /// The default foreground colour, used by reversed text.
+    DefaultFg,
+    /// The default background colour, used by reversed text.
+    DefaultBg,
+}
+
+/// The foreground and background paints of the style, swapped if the style is reversed.
+/// A hidden style has no foreground paint, it is transparent instead.
+fn paints(style: Style) -> (Option<Paint>, Option<Paint>) {
+    let (fg, bg) = if style.reversed == Some(true) {
+        (
+            Some(style.bg.map_or(Paint::DefaultBg, Paint::Colour)),
+            Some(style.fg.map_or(Paint::DefaultFg, Paint::Colour)),
+        )
+    } else {
+        (style.fg.map(Paint::Colour), style.bg.map(Paint::Colour))
+    };
+
+    if style.hidden == Some(true) {
+        (None, bg)
+    } else {
+        (fg, bg)
+    }
+}
+
+/// Write the ` class=` (and ` style=` for 24-bit colours) attributes of the style.
+fn write_classes<W: Write>(w: &mut W, style: Style) -> fmt::Result {
+    let (fg, bg) = paints(style);
+    let mut classes = Classes { w, empty: true };
+
+    for (paint, ground) in [(fg, "fg"), (bg, "bg")] {
+        match paint {
+            Some(Paint::Colour(Color::Indexed(i))) => {
+                classes.push(format_args!("ansi-{}-{}", ground, i))?
+            }
+            Some(Paint::Colour(Color::Rgb(..))) | None => (),
+            Some(Paint::Colour(colour)) => classes.push(format_args!(
+                "ansi-{}-{}",
+                ground,
+                NAMES[standard_index(colour)]
+            ))?,
+            Some(Paint::DefaultFg) | Some(Paint::DefaultBg) => {
+                classes.push(format_args!("ansi-{}-reversed", ground))?
+            }
+        }
+    }
+
+    let flags = [
+        (style.intensity == Some(Intensity::Bold), "ansi-bold"),
+        (style.intensity == Some(Intensity::Faint), "ansi-faint"),
+        (style.italic == Some(true), "ansi-italic"),
+        (style.underline == Some(true), "ansi-underline"),
+        (style.blink == Some(true), "ansi-blink"),
+        (style.strikethrough == Some(true), "ansi-strikethrough"),
+        (style.hidden == Some(true), "ansi-hidden"),
+    ];
+    for (_, class) in flags.iter().filter(|(on, _)| *on) {
+        classes.push(format_args!("{}", class))?;
+    }
+
+    let w = classes.finish()?;
+
+    // 24-bit colours do not have classes
+    let rgb = |paint| match paint {
+        Some(Paint::Colour(Color::Rgb(r, g, b))) => Some(Hex((r, g, b))),
+        _ => None,
+    };
+    match (rgb(fg), rgb(bg)) {
+        (None, None) => Ok(()),
+        (fg, bg) => {
+            w.write_str(" style=\"")?;
+            if let Some(fg) = fg {
+                write!(w, "color:{};", fg)?;
+            }
+            if let Some(bg) = bg {
+                write!(w, "background-color:{};", bg)?;
+            }
+            w.write_char('"')
+        }
+    }
+}
+
+/// Writes the space separated classes of a ` class=` attribute.
+struct Classes<'w, W> {
+    w: &'w mut W,
+    empty: bool,
+}
+
+impl<'w, W: Write> Classes<'w, W> {
+    fn push(&mut self, class: fmt::Arguments) -> fmt::Result {
+        self.w
+            .write_str(if self.empty { " class=\"" } else { " " })?;
+        self.empty = false;
+        self.w.write_fmt(class)
+    }
+
+    fn finish(self) -> Result<&'w mut W, fmt::Error> {
+        if !self.empty {
+            self.w.write_char('"')?;
+        }
+        Ok(self.w)
+    }
+}
+
+/// Write the inline CSS declarations of the style.
+fn write_declarations<W: Write>(w: &mut W, style: Style, palette: &Palette) -> fmt::Result {
+    let (fg, bg) = paints(style);
+
+    if let Some(fg) = fg {
+        write!(w, "color:{};", Hex(paint_rgb(fg, palette)))?;
+    } else if style.hidden == Some(true) {
+        w.write_str("color:transparent;")?;
+    }
+    if let Some(bg) = bg {
+        write!(w, "background-color:{};", Hex(paint_rgb(bg, palette)))?;
+    }
+
+    match style.intensity {
+        Some(Intensity::Bold) => w.write_str("font-weight:bold;")?,
+        Some(Intensity::Faint) => w.write_str("opacity:0.5;")?,
+        Some(Intensity::Normal) | None => (),
+    }
+    if style.italic == Some(true) {
+        w.write_str("font-style:italic;")?;
+    }
+    match (
+        style.underline == Some(true),
+        style.strikethrough == Some(true),
+    ) {
+        (true, true) => w.write_str("text-decoration-line:underline line-through;")?,
+        (true, false) => w.write_str("text-decoration-line:underline;")?,
+        (false, true) => w.write_str("text-decoration-line:line-through;")?,
+        (false, false) => (),
+    }
+    if style.blink == Some(true) {
+        w.write_str("animation:ansi-blink 1s steps(1) infinite;")?;
+    }
+
+    Ok(())
+}
+
+/// Write the text with the HTML (and XML) special characters escaped.
+pub(crate) fn write_escaped<W: Write>(w: &mut W, text: &str) -> fmt::Result {
+    let mut lo = 0;
+    for (i, c) in text.char_indices() {
+        let escaped = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            '"' => "&quot;",
+            '\'' => "&#39;",
+            _ => continue,
+        };
+        w.write_str(&text[lo..i])?;
+        w.write_str(escaped)?;
+        lo = i + 1;
+    }
+    w.write_str(&text[lo..])
+}
+
+fn paint_rgb(paint: Paint, palette: &Palette) -> (u8, u8, u8) {
+    match paint {
+        Paint::Colour(colour) => palette.rgb(colour),
+        Paint::DefaultFg => palette.foreground,
+        Paint::DefaultBg => palette.background,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v3::categorise_text;
+
+    fn inline(text: &str) -> String {
+        Html::inline(&categorise_text(text)).to_string()
+    }
+
+    fn classes(text: &str) -> String {
+        Html::classes(&categorise_text(text)).to_string()
+    }
+
+    #[test]
+    fn escapes_text() {
+        let text = "<a href=\"x\">'&'</a>";
+        let escaped = "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;";
+        assert_eq!(inline(text), escaped);
+        assert_eq!(
+            inline(&format!("\x1b[3m{}", text)),
+            format!("<span style=\"font-style:italic;\">{}</span>", escaped)
+        );
+        assert_eq!(inline("\x1b[1m\x1b[0m"), "");
+    }
+
+    #[test]
+    fn inline_styles() {
+        assert_eq!(
+            inline("\x1b[38;5;208;48;2;1;2;3;2mx"),
+            "<span style=\"color:#ff8700;background-color:#010203;opacity:0.5;\">x</span>"
+        );
+        assert_eq!(
+            inline("\x1b[4;9;5mx"),
+            "<span style=\"text-decoration-line:underline line-through;\
+             animation:ansi-blink 1s steps(1) infinite;\">x</span>"
+        );
+        assert_eq!(
+            inline("\x1b[7mx\x1b[31mx"),
+            "<span style=\"color:#000000;background-color:#e5e5e5;\">x</span>\
+             <span style=\"color:#000000;background-color:#cd0000;\">x</span>"
+        );
+        assert_eq!(
+            inline("\x1b[8;31;44mx"),
+            "<span style=\"color:transparent;background-color:#0000ee;\">x</span>"
+        );
+    }
+
+    #[test]
+    fn inline_palette() {
+        let slices = categorise_text("\x1b[31mred\x1b[0;7mrev");
+        assert_eq!(
+            Html::inline(&slices).palette(Palette::VGA).to_string(),
+            "<span style=\"color:#aa0000;\">red</span>\
+             <span style=\"color:#000000;background-color:#aaaaaa;\">rev</span>"
+        );
+    }
+
+    #[test]
+    fn class_styles() {
+        assert_eq!(
+            classes("\x1b[38;5;208;48;2;1;2;3;2mx"),
+            "<span class=\"ansi-fg-208 ansi-faint\" style=\"background-color:#010203;\">x</span>"
+        );
+        assert_eq!(
+            classes("\x1b[4;9;5mx"),
+            "<span class=\"ansi-underline ansi-blink ansi-strikethrough\">x</span>"
+        );
+        assert_eq!(
+            classes("\x1b[7mx\x1b[91mx"),
+            "<span class=\"ansi-fg-reversed ansi-bg-reversed\">x</span>\
+             <span class=\"ansi-fg-reversed ansi-bg-bright-red\">x</span>"
+        );
+        assert_eq!(
+            classes("\x1b[8;38;2;1;2;3mx"),
+            "<span class=\"ansi-hidden\">x</span>"
+        );
+    }
+
+    #[test]
+    fn stylesheet_defines_classes() {
+        let css = Stylesheet::default().to_string();
+        for class in [
+            "ansi-bold",
+            "ansi-faint",
+            "ansi-italic",
+            "ansi-underline",
+            "ansi-blink",
+            "ansi-strikethrough",
+            "ansi-hidden",
+            "ansi-fg-reversed",
+            "ansi-bg-reversed",
+            "ansi-fg-bright-white",
+            "ansi-bg-black",
+            "ansi-fg-0",
+            "ansi-bg-255",
+        ] {
+            assert!(css.contains(&format!(".{} {{", class)), "{}", class);
+        }
+    }
+}
@@ -9,10 +9,12 @@
 //! Look at progress and contribute on [github.](https://github.com/kurtlawrence/cansi)
 //!
 //! `cansi` will parse text with ANSI escape sequences in it and return a deconstructed
-//! text with metadata around the colouring and styling. `cansi` is only concerned
-//! with `CSI` sequences, particuarly the `SGR` parameters. `cansi` will not construct
-//! escaped text, there are crates such as [`colored`](https://crates.io/crates/colored)
-//! that do a great job of colouring and styling text.
+//! text with metadata around the colouring and styling. All escape sequences (`CSI`, `OSC`,
+//! `DCS` and friends) are removed from the text, but `cansi` is only concerned with the
+//! styling of `CSI` sequences, particuarly the `SGR` parameters. Categorised text can be
+//! constructed back into escaped text with `v3::construct_text_with_codes`, `v3::StyledText` or
+//! `v3::normalise`, but to colour and style text from scratch there are crates such as
+//! [`colored`](https://crates.io/crates/colored) that do a great job of it.
 //!
 //! # Example usage
 //!
@@ -84,6 +86,12 @@
 //! );
 //! ```
 //!
+//! ## Upgrading to 3.0
+//! `Color` has the `Indexed(u8)` and `Rgb(u8, u8, u8)` variants for 256 colour and 24-bit
+//! colours, which were previously left without a colour. `Color` is now `#[non_exhaustive]`, so a
+//! `match` on it needs a wildcard arm. The deprecated functions outside of `v3` are kept, and will
+//! be removed in a future major version.
+//!
 //! ## Targeting no_std
 //! This crate can use `alloc` in place of the standard library for no_std targets.
 //! The standard library is enabled by default, so disabling default features and enabling the
@@ -91,7 +99,17 @@
 //!
 //! ```toml
 //! [dependencies]
-//! cansi = { version = "2.1.0", default-features = false, features = ["alloc"] }
+//! cansi = { version = "3.0.0", default-features = false, features = ["alloc"] }
+//! ```
+//!
+//! Without an allocator, disable default features and leave out `alloc`.
+//! The functions returning `Vec`s and `String`s are not available, but the core parser and
+//! categoriser are, through iterators (`parse_iter`, `v3::categorise_iter`, and their byte
+//! counterparts), callbacks (`v3::categorise_with`), and writers (`v3::write_text_no_codes`).
+//!
+//! ```toml
+//! [dependencies]
+//! cansi = { version = "3.0.0", default-features = false }
 //! ```
 
 #![cfg_attr(not(feature = "std"), no_std)]
@@ -106,18 +124,44 @@ use alloc::string::String;
 use alloc::vec::Vec;
 
 mod categorise;
+mod charset;
+#[cfg(feature = "alloc")]
+mod collapse;
+mod construct;
+#[cfg(feature = "std")]
+mod detect;
+mod downsample;
+mod html;
+#[cfg(feature = "alloc")]
+mod offsets;
+mod palette;
 mod parsing;
+#[cfg(feature = "alloc")]
+mod screen;
+#[cfg(feature = "alloc")]
+mod svg;
 
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "alloc")]
+mod truncate;
+mod width;
+#[cfg(feature = "alloc")]
+mod wrap;
 
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
 pub use categorise::categorise_text;
-pub use parsing::{parse, Match};
+#[cfg(feature = "alloc")]
+pub use parsing::{parse, parse_bytes};
+pub use parsing::{
+    parse_bytes_iter, parse_iter, ByteMatch, ByteMatchIter, Match, MatchIter, SequenceKind,
+};
 
 /// Type definition of the collection of `CategorisedSlice`s.
 #[deprecated = "please use v3::CategorisedSlices to move to API v3.0. \
-                this function will be removed with v3.0 of cansi"]
+                this function will be removed in a future major version of cansi"]
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
 pub type CategorisedSlices<'text> = Vec<CategorisedSlice<'text>>;
 
@@ -130,7 +174,8 @@ pub type CategorisedSlices<'text> = Vec<CategorisedSlice<'text>>;
 /// assert_eq!("Hello", &construct_text_no_codes(&categorised));
 /// ```
 #[deprecated = "please use v3::construct_text_no_codes to move to API v3.0. \
-                this function will be removed with v3.0 of cansi"]
+                this function will be removed in a future major version of cansi"]
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
 pub fn construct_text_no_codes(categorised_slices: &CategorisedSlices) -> String {
     let x = categorised_slices.iter().cloned().map(Into::into).collect();
@@ -162,7 +207,8 @@ pub fn construct_text_no_codes(categorised_slices: &CategorisedSlices) -> String
 /// assert_eq!(iter.next(), None);
 /// ```
 #[deprecated = "please use v3::line_iter to move to API v3.0. \
-                this function will be removed with v3.0 of cansi"]
+                this function will be removed in a future major version of cansi"]
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
 pub fn line_iter<'text, 'iter>(
     categorised_slices: &'iter CategorisedSlices<'text>,
@@ -199,7 +245,8 @@ pub fn line_iter<'text, 'iter>(
 /// assert_eq!(iter.next(), None);
 /// ```
 #[deprecated = "please use v3::CategorisedLineIterator to move to API v3.0. \
-                this function will be removed with v3.0 of cansi"]
+                this function will be removed in a future major version of cansi"]
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
 pub struct CategorisedLineIterator<'text, 'iter> {
     slices: &'iter CategorisedSlices<'text>,
@@ -212,10 +259,12 @@ pub struct CategorisedLineIterator<'text, 'iter> {
 /// # Note
 /// > The type alias is the same as `CategorisedSlices`, so functions such as `construct_text_no_codes` will work.
 #[deprecated = "please use v3::CategorisedLine to move to API v3.0. \
-                this function will be removed with v3.0 of cansi"]
+                this function will be removed in a future major version of cansi"]
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
 pub type CategorisedLine<'text> = Vec<CategorisedSlice<'text>>;
 
+#[cfg(feature = "alloc")]
 #[allow(deprecated)]
 impl<'text, 'iter> Iterator for CategorisedLineIterator<'text, 'iter> {
     type Item = CategorisedLine<'text>;
@@ -250,7 +299,9 @@ impl<'text, 'iter> Iterator for CategorisedLineIterator<'text, 'iter> {
 
             // push first slice on -- only if not empty
             if first > 0 || v.is_empty() {
-                v.push(slice.clone_style(&slice.text[..first], slice.start, slice.start + first));
+                // a slice without a new line is kept whole, its text may be translated
+                let end = remainder.map_or(slice.end, |_| slice.start + first);
+                v.push(slice.clone_style(&slice.text[..first], slice.start, end));
             }
 
             if let Some(remainder) = remainder {
@@ -278,6 +329,7 @@ impl<'text, 'iter> Iterator for CategorisedLineIterator<'text, 'iter> {
 /// Splits on the first instance of `\r\n` or `\n` bytes.
 /// Returns the _exclusive_ end of the first componenet, and the _inclusive_ start of the remaining items if there is a split.
 /// Can return an empty remainder slice (if terminated with a new line). Can return empty first slice (say `"\nHello"`);
+#[cfg(feature = "alloc")]
 fn split_on_new_line(txt: &str) -> (usize, Option<usize>) {
     let cr = txt.find('\r');
     let nl = txt.find('\n');
@@ -299,7 +351,7 @@ fn split_on_new_line(txt: &str) -> (usize, Option<usize>) {
 /// Data structure that holds information about colouring and styling of a text slice.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[deprecated = "please use v3::CategorisedSlice to move to API v3.0. \
-                this function will be removed with v3.0 of cansi"]
+                this function will be removed in a future major version of cansi"]
 pub struct CategorisedSlice<'text> {
     /// The text slice.
     pub text: &'text str,
@@ -333,6 +385,7 @@ pub struct CategorisedSlice<'text> {
 
 #[allow(deprecated)]
 impl<'text> CategorisedSlice<'text> {
+    #[cfg(feature = "alloc")]
     const fn clone_style(&self, text: &'text str, start: usize, end: usize) -> Self {
         let mut c = *self;
         c.text = text;
@@ -343,7 +396,7 @@ impl<'text> CategorisedSlice<'text> {
 
     #[cfg(test)]
     fn default_style(text: &'text str, start: usize, end: usize) -> Self {
-        v3::CategorisedSlice::with_sgr(SGR::default(), text, start, end).into()
+        v3::CategorisedSlice::with_sgr(Style::default(), text, start, end).into()
     }
 }
 
@@ -420,22 +473,60 @@ impl<'a> From<CategorisedSlice<'a>> for v3::CategorisedSlice<'a> {
 
 /// The formatting components `SGR (Select Graphic Rendition)`.
 /// [spec](https://en.wikipedia.org/wiki/ANSI_escape_code#SGR_(Select_Graphic_Rendition)_parameters)
-#[derive(Clone, Copy, Default)]
-#[allow(clippy::upper_case_acronyms)]
-struct SGR {
-    fg: Option<Color>,
-    bg: Option<Color>,
-    intensity: Option<Intensity>,
-    italic: Option<bool>,
-    underline: Option<bool>,
-    blink: Option<bool>,
-    reversed: Option<bool>,
-    hidden: Option<bool>,
-    strikethrough: Option<bool>,
+///
+/// This is the style of a `v3::CategorisedSlice`, without the text. `None` is the terminal's
+/// default for that attribute.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub struct Style {
+    /// The foreground (or text) colour.
+    pub fg: Option<Color>,
+    /// The background colour.
+    pub bg: Option<Color>,
+
+    /// The emphasis state (bold, faint, normal).
+    pub intensity: Option<Intensity>,
+
+    /// Italicised.
+    pub italic: Option<bool>,
+    /// Underlined.
+    pub underline: Option<bool>,
+
+    /// Slow blink text.
+    pub blink: Option<bool>,
+    /// Inverted colours. See [https://en.wikipedia.org/wiki/Reverse_video](https://en.wikipedia.org/wiki/Reverse_video).
+    pub reversed: Option<bool>,
+    /// Invisible text.
+    pub hidden: Option<bool>,
+    /// Struck-through.
+    pub strikethrough: Option<bool>,
+}
+
+impl Style {
+    /// The style with the attributes that are explicitly off (`Some(false)`,
+    /// `Some(Intensity::Normal)`) set to `None`, as they render the same as the default.
+    ///
+    /// Two styles that render the same have equal normalised forms.
+    pub fn normalised(self) -> Self {
+        fn on(x: Option<bool>) -> Option<bool> {
+            x.filter(|x| *x)
+        }
+
+        Self {
+            fg: self.fg,
+            bg: self.bg,
+            intensity: self.intensity.filter(|x| *x != Intensity::Normal),
+            italic: on(self.italic),
+            underline: on(self.underline),
+            blink: on(self.blink),
+            reversed: on(self.reversed),
+            hidden: on(self.hidden),
+            strikethrough: on(self.strikethrough),
+        }
+    }
 }
 
 /// The emphasis (bold, faint) states.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Intensity {
     /// Normal intensity (no emphasis).
     Normal,
@@ -445,9 +536,12 @@ pub enum Intensity {
     Faint,
 }
 
-/// The 8 standard colors.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The 8 standard colors, their bright variants, and the extended colour forms.
+///
+/// More colour forms may be added, so matches need a wildcard arm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[allow(missing_docs)]
+#[non_exhaustive]
 pub enum Color {
     Black,
     Red,
@@ -465,6 +559,13 @@ pub enum Color {
     BrightMagenta,
     BrightCyan,
     BrightWhite,
+    /// An entry of the 256 colour palette (`38;5;n` / `48;5;n`).
+    ///
+    /// Indices 0-15 are the standard and bright colours, 16-231 a 6x6x6 colour cube, and
+    /// 232-255 a grayscale ramp. Indices are _not_ folded into the named variants.
+    Indexed(u8),
+    /// A 24-bit direct colour (`38;2;r;g;b` / `48;2;r;g;b`).
+    Rgb(u8, u8, u8),
 }
 
 /// Update API for version 3.0 of the crate.
@@ -473,10 +574,49 @@ pub enum Color {
 /// only require a change in import code.
 /// Note that version 3.0 will remove the deprecated version 2.0 items.
 pub mod v3 {
-    use super::{split_on_new_line, SGR};
-    pub use crate::{Color, Intensity};
-
+    #[cfg(feature = "alloc")]
+    use super::split_on_new_line;
+    pub use crate::{Color, Intensity, Style};
+    #[cfg(all(not(feature = "std"), feature = "alloc"))]
+    use alloc::{string::String, vec::Vec};
+
+    #[cfg(feature = "alloc")]
+    pub use super::categorise::categorise_bytes;
+    #[cfg(feature = "alloc")]
+    pub use super::categorise::categorise_text_and_controls_v3 as categorise_text_and_controls;
+    #[cfg(feature = "alloc")]
     pub use super::categorise::categorise_text_v3 as categorise_text;
+    #[cfg(feature = "alloc")]
+    pub use super::categorise::Categoriser;
+    pub use super::categorise::{
+        categorise_bytes_iter, categorise_iter, categorise_with, CategorisedByteIter,
+        CategorisedIter,
+    };
+    #[cfg(feature = "alloc")]
+    pub use super::charset::decode_charsets;
+    #[cfg(feature = "alloc")]
+    pub use super::collapse::collapse;
+    #[cfg(feature = "std")]
+    pub use super::construct::construct_text_for_terminal;
+    #[cfg(feature = "alloc")]
+    pub use super::construct::{construct_text_for_level, construct_text_with_codes, normalise};
+    pub use super::construct::{StyleTransition, StyledText};
+    #[cfg(feature = "std")]
+    pub use super::detect::{color_level_from_env, detect_color_level};
+    pub use super::downsample::{downsample, ColorLevel};
+    pub use super::html::{Html, Stylesheet};
+    #[cfg(feature = "alloc")]
+    pub use super::offsets::OffsetMap;
+    pub use super::palette::Palette;
+    #[cfg(feature = "alloc")]
+    pub use super::screen::Screen;
+    #[cfg(feature = "alloc")]
+    pub use super::svg::Svg;
+    #[cfg(feature = "alloc")]
+    pub use super::truncate::{truncate, truncate_ansi};
+    pub use super::width::{ansi_width, slices_width, text_width};
+    #[cfg(feature = "alloc")]
+    pub use super::wrap::{wrap, Wrapper};
 
     /// Data structure that holds information about colouring and styling of a text slice.
     #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -512,8 +652,13 @@ pub mod v3 {
     }
 
     impl<'text> CategorisedSlice<'text> {
-        pub(crate) const fn with_sgr(sgr: SGR, text: &'text str, start: usize, end: usize) -> Self {
-            let SGR {
+        pub(crate) const fn with_sgr(
+            sgr: Style,
+            text: &'text str,
+            start: usize,
+            end: usize,
+        ) -> Self {
+            let Style {
                 fg,
                 bg,
                 intensity,
@@ -541,6 +686,22 @@ pub mod v3 {
             }
         }
 
+        /// The style of the slice.
+        pub const fn style(&self) -> Style {
+            Style {
+                fg: self.fg,
+                bg: self.bg,
+                intensity: self.intensity,
+                italic: self.italic,
+                underline: self.underline,
+                blink: self.blink,
+                reversed: self.reversed,
+                hidden: self.hidden,
+                strikethrough: self.strikethrough,
+            }
+        }
+
+        #[cfg(feature = "alloc")]
         const fn clone_style(&self, text: &'text str, start: usize, end: usize) -> Self {
             let mut c = *self;
             c.text = text;
@@ -550,18 +711,54 @@ pub mod v3 {
         }
 
         #[cfg(test)]
-        fn default_style(text: &'text str, start: usize, end: usize) -> Self {
-            Self::with_sgr(SGR::default(), text, start, end)
+        pub(crate) fn default_style(text: &'text str, start: usize, end: usize) -> Self {
+            Self::with_sgr(Style::default(), text, start, end)
         }
     }
 
     /// Type definition of the collection of `CategorisedSlice`s.
+    #[cfg(feature = "alloc")]
     pub type CategorisedSlices<'text> = Vec<CategorisedSlice<'text>>;
 
+    /// Data structure that holds information about colouring and styling of a byte slice.
+    /// The byte counterpart of `CategorisedSlice`, see `categorise_bytes`.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct CategorisedByteSlice<'text> {
+        /// The byte slice.
+        pub text: &'text [u8],
+        /// _Inclusive_ starting byte position.
+        pub start: usize,
+        /// _Exclusive_ ending byte position.
+        pub end: usize,
+        /// The colouring and styling of the slice.
+        pub style: Style,
+    }
+
+    impl<'text> CategorisedByteSlice<'text> {
+        pub(crate) const fn with_sgr(
+            sgr: Style,
+            text: &'text [u8],
+            start: usize,
+            end: usize,
+        ) -> Self {
+            Self {
+                text,
+                start,
+                end,
+                style: sgr,
+            }
+        }
+    }
+
+    /// Type definition of the collection of `CategorisedByteSlice`s.
+    #[cfg(feature = "alloc")]
+    pub type CategorisedByteSlices<'text> = Vec<CategorisedByteSlice<'text>>;
+
     /// The item type of `CategorisedLineIterator`.
     ///
     /// # Note
     /// > The type alias is the same as `CategorisedSlices`, so functions such as `construct_text_no_codes` will work.
+    #[cfg(feature = "alloc")]
     pub type CategorisedLine<'text> = Vec<CategorisedSlice<'text>>;
 
     /// Construct an iterator over each new line (`\n` or `\r\n`) and returns the categorised slices within those.
@@ -588,6 +785,7 @@ pub mod v3 {
     /// assert_eq!(&construct_text_no_codes(&iter.next().unwrap()), "today");
     /// assert_eq!(iter.next(), None);
     /// ```
+    #[cfg(feature = "alloc")]
     pub fn line_iter<'text, 'iter>(
         categorised_slices: &'iter CategorisedSlices<'text>,
     ) -> CategorisedLineIterator<'text, 'iter> {
@@ -622,12 +820,14 @@ pub mod v3 {
     /// assert_eq!(&construct_text_no_codes(&iter.next().unwrap()), "today");
     /// assert_eq!(iter.next(), None);
     /// ```
+    #[cfg(feature = "alloc")]
     pub struct CategorisedLineIterator<'text, 'iter> {
         slices: &'iter CategorisedSlices<'text>,
         idx: usize,
         prev: Option<CategorisedSlice<'text>>,
     }
 
+    #[cfg(feature = "alloc")]
     impl<'text, 'iter> Iterator for CategorisedLineIterator<'text, 'iter> {
         type Item = CategorisedLine<'text>;
         fn next(&mut self) -> Option<Self::Item> {
@@ -661,11 +861,9 @@ pub mod v3 {
 
                 // push first slice on -- only if not empty
                 if first > 0 || v.is_empty() {
-                    v.push(slice.clone_style(
-                        &slice.text[..first],
-                        slice.start,
-                        slice.start + first,
-                    ));
+                    // a slice without a new line is kept whole, its text may be translated
+                    let end = remainder.map_or(slice.end, |_| slice.start + first);
+                    v.push(slice.clone_style(&slice.text[..first], slice.start, end));
                 }
 
                 if let Some(remainder) = remainder {
@@ -690,6 +888,26 @@ pub mod v3 {
         }
     }
 
+    /// Writes the text without the ANSI escape characters to `w`.
+    ///
+    /// Unlike `construct_text_no_codes`, this does not allocate, so works without the `std` or
+    /// `alloc` features.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cansi::v3::*;
+    /// let mut s = String::new();
+    /// write_text_no_codes("\x1b[30mH\x1b[31me\x1b]0;title\x07llo", &mut s).unwrap();
+    /// assert_eq!("Hello", &s);
+    /// ```
+    pub fn write_text_no_codes<W: core::fmt::Write>(text: &str, w: &mut W) -> core::fmt::Result {
+        for slice in categorise_iter(text) {
+            w.write_str(slice.text)?;
+        }
+
+        Ok(())
+    }
+
     /// Constructs a string of the categorised text without the ANSI escape characters.
     ///
     /// # Example
@@ -698,6 +916,7 @@ pub mod v3 {
     /// let categorised = categorise_text("\x1b[30mH\x1b[31me\x1b[32ml\x1b[33ml\x1b[34mo");
     /// assert_eq!("Hello", &construct_text_no_codes(&categorised));
     /// ```
+    #[cfg(feature = "alloc")]
     pub fn construct_text_no_codes(categorised_slices: &CategorisedSlices) -> String {
         let slices = categorised_slices;
         let mut s = String::with_capacity(
@@ -0,0 +1,317 @@
+use super::*;
+use core::ops::Range;
+
+/// Maps positions in the visible text of categorised slices to positions in the original text,
+/// and back.
+///
+/// The visible text is the slices' text joined together, as constructed by
+/// [`v3::construct_text_no_codes`](crate::v3::construct_text_no_codes). Positions are byte
+/// offsets unless noted, and [`OffsetMap::char_to_byte`] and [`OffsetMap::byte_to_char`]
+/// convert between byte and `char` offsets in the visible text. Each lookup is a binary search,
+/// taking `O(log n)` time in the number of slices (or non-ASCII characters for `char` offsets).
+///
+/// Where the visible text continues past escape codes, a visible offset at the boundary maps to
+/// the start of the next slice, after the codes. Use [`OffsetMap::to_raw_range`] to map a match,
+/// which ends a range before the codes instead.
+///
+/// A slice's position in the original text is its `start..end`, which for a character translated
+/// from the DEC Special Graphics set is shorter than its text. A visible offset within such a
+/// character maps to the start of the original character.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let text = "error: \x1b[1;31mfile not found\x1b[0m (exit 2)";
+/// let slices = categorise_text(text);
+/// let visible = construct_text_no_codes(&slices);
+/// let map = OffsetMap::new(&slices);
+///
+/// let found = visible.find("not found").unwrap();
+/// let raw = map.to_raw_range(found..found + 9).unwrap();
+/// assert_eq!(&text[raw], "not found");
+///
+/// let i = map.slice_index(found).unwrap();
+/// assert_eq!(slices[i].fg, Some(Color::Red));
+///
+/// assert_eq!(map.to_visible(text.find("(exit").unwrap()), Some(visible.find("(exit").unwrap()));
+/// assert_eq!(map.to_visible(9), None); // within an escape sequence
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OffsetMap {
+    /// The non-empty slices, in order.
+    spans: Vec<Span>,
+    /// The non-ASCII characters of the visible text, in order.
+    chars: Vec<Char>,
+    /// The length of the visible text in bytes.
+    len: usize,
+    /// The length of the visible text in `char`s.
+    char_len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    /// The index of the slice.
+    slice: usize,
+    /// The byte position in the visible text.
+    visible: usize,
+    /// The byte position in the original text.
+    raw: usize,
+    /// The length in the visible text.
+    len: usize,
+    /// The length in the original text.
+    raw_len: usize,
+}
+
+impl Span {
+    /// The position in the original text of an `offset` into the visible span.
+    fn raw_at(&self, offset: usize) -> usize {
+        if self.len == self.raw_len {
+            self.raw + offset
+        } else if offset < self.len {
+            self.raw
+        } else {
+            self.raw + self.raw_len
+        }
+    }
+
+    /// The position in the visible text of an `offset` into the original span.
+    fn visible_at(&self, offset: usize) -> usize {
+        if self.len == self.raw_len {
+            self.visible + offset
+        } else if offset < self.raw_len {
+            self.visible
+        } else {
+            self.visible + self.len
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Char {
+    /// The byte position in the visible text.
+    byte: usize,
+    /// The `char` position in the visible text.
+    index: usize,
+    len: usize,
+}
+
+impl OffsetMap {
+    /// Build the map from slices in order, as returned by `categorise_text`.
+    pub fn new(slices: &[v3::CategorisedSlice]) -> Self {
+        let mut map = Self::default();
+        for (slice, s) in slices.iter().enumerate() {
+            if s.text.is_empty() {
+                continue;
+            }
+
+            map.spans.push(Span {
+                slice,
+                visible: map.len,
+                raw: s.start,
+                len: s.text.len(),
+                raw_len: s.end - s.start,
+            });
+            for (i, c) in s.text.char_indices() {
+                if !c.is_ascii() {
+                    map.chars.push(Char {
+                        byte: map.len + i,
+                        index: map.char_len,
+                        len: c.len_utf8(),
+                    });
+                }
+                map.char_len += 1;
+            }
+            map.len += s.text.len();
+        }
+        map
+    }
+
+    /// The length of the visible text in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there is no visible text.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The length of the visible text in `char`s.
+    pub fn char_len(&self) -> usize {
+        self.char_len
+    }
+
+    /// The position in the original text of the `visible` position, or `None` if it is past the
+    /// end of the visible text.
+    pub fn to_raw(&self, visible: usize) -> Option<usize> {
+        if visible == self.len {
+            return Some(self.spans.last().map(|s| s.raw + s.raw_len).unwrap_or(0));
+        }
+        let span = self.span_at(visible)?;
+        Some(span.raw_at(visible - span.visible))
+    }
+
+    /// The range in the original text of a range of the visible text, or `None` if it is out of
+    /// bounds.
+    ///
+    /// The range starts after any escape codes before it and ends before any after it, so it
+    /// holds only the escape codes _within_ the visible range.
+    pub fn to_raw_range(&self, visible: Range<usize>) -> Option<Range<usize>> {
+        let start = self.to_raw(visible.start)?;
+        if visible.end <= visible.start {
+            return Some(start..start);
+        }
+        let span = self.span_at(visible.end - 1)?;
+        Some(start..span.raw_at(visible.end - span.visible))
+    }
+
+    /// The position in the visible text of the `raw` position in the original text, or `None` if
+    /// it is within an escape sequence or out of bounds.
+    ///
+    /// The position just after a slice, where an escape sequence starts, is still visible.
+    pub fn to_visible(&self, raw: usize) -> Option<usize> {
+        let i = self.spans.partition_point(|s| s.raw <= raw);
+        let span = self.spans.get(i.checked_sub(1)?)?;
+        if raw <= span.raw + span.raw_len {
+            Some(span.visible_at(raw - span.raw))
+        } else {
+            None
+        }
+    }
+
+    /// The index of the slice holding the byte at the `visible` position, or `None` if it is past
+    /// the end of the visible text.
+    pub fn slice_index(&self, visible: usize) -> Option<usize> {
+        self.span_at(visible).map(|s| s.slice)
+    }
+
+    /// The byte position of the `char` position in the visible text, or `None` if it is past the
+    /// end.
+    pub fn char_to_byte(&self, index: usize) -> Option<usize> {
+        if index > self.char_len {
+            return None;
+        }
+        let i = self.chars.partition_point(|c| c.index < index);
+        match i.checked_sub(1).map(|i| self.chars[i]) {
+            // ASCII after the last non-ASCII character
+            Some(c) => Some(c.byte + c.len + index - c.index - 1),
+            None => Some(index),
+        }
+    }
+
+    /// The `char` position of the byte position in the visible text, or `None` if it is past the
+    /// end or not on a `char` boundary.
+    pub fn byte_to_char(&self, byte: usize) -> Option<usize> {
+        if byte > self.len {
+            return None;
+        }
+        let i = self.chars.partition_point(|c| c.byte < byte);
+        match i.checked_sub(1).map(|i| self.chars[i]) {
+            Some(c) if byte < c.byte + c.len => None,
+            Some(c) => Some(c.index + 1 + byte - c.byte - c.len),
+            None => Some(byte),
+        }
+    }
+
+    /// The span holding the byte at the `visible` position.
+    fn span_at(&self, visible: usize) -> Option<&Span> {
+        if visible >= self.len {
+            return None;
+        }
+        let i = self.spans.partition_point(|s| s.visible <= visible);
+        self.spans.get(i - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_offsets() {
+        let text = "ab\x1b[31mcd\x1b[0m\x1b[4mef";
+        let slices = v3::categorise_text(text);
+        let map = OffsetMap::new(&slices);
+
+        assert_eq!(map.len(), 6);
+        let raw: Vec<_> = (0..=6).map(|v| map.to_raw(v).unwrap()).collect();
+        assert_eq!(raw, [0, 1, 7, 8, 17, 18, 19]);
+        assert_eq!(map.to_raw(7), None);
+
+        for (v, &r) in raw.iter().enumerate() {
+            assert_eq!(map.to_visible(r), Some(v));
+        }
+        assert_eq!(map.to_visible(2), Some(2)); // the end of "ab"
+        assert_eq!(map.to_visible(3), None);
+        assert_eq!(map.to_visible(12), None);
+        assert_eq!(map.to_visible(20), None);
+
+        assert_eq!(map.to_raw_range(1..3), Some(1..8));
+        assert_eq!(map.to_raw_range(2..4), Some(7..9));
+        assert_eq!(&text[map.to_raw_range(0..6).unwrap()], text);
+        assert_eq!(map.to_raw_range(4..4), Some(17..17));
+        assert_eq!(map.to_raw_range(4..7), None);
+    }
+
+    #[test]
+    fn slice_indices() {
+        let slices = v3::categorise_text("\x1b[31mab\x1b[32m\x1b[33mc");
+        let map = OffsetMap::new(&slices);
+
+        let indices: Vec<_> = (0..4).map(|v| map.slice_index(v)).collect();
+        let c = slices.iter().position(|s| s.text == "c");
+        assert_eq!(indices, [Some(0), Some(0), c, None]);
+
+        let empty = OffsetMap::new(&[]);
+        assert!(empty.is_empty());
+        assert_eq!(empty.to_raw(0), Some(0));
+        assert_eq!(empty.to_visible(0), None);
+        assert_eq!(empty.slice_index(0), None);
+        assert_eq!(empty.char_to_byte(0), Some(0));
+    }
+
+    #[test]
+    fn maps_chars() {
+        let slices = v3::categorise_text("aé\x1b[1m中b\x1b[0m😀");
+        let map = OffsetMap::new(&slices);
+        let visible = v3::construct_text_no_codes(&slices);
+
+        assert_eq!(map.char_len(), 5);
+        let bytes: Vec<_> = visible
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(visible.len()))
+            .collect();
+        for (c, &b) in bytes.iter().enumerate() {
+            assert_eq!(map.char_to_byte(c), Some(b));
+            assert_eq!(map.byte_to_char(b), Some(c));
+        }
+        assert_eq!(map.char_to_byte(6), None);
+        assert_eq!(map.byte_to_char(2), None);
+        assert_eq!(map.byte_to_char(4), None);
+        assert_eq!(map.byte_to_char(visible.len() + 1), None);
+    }
+
+    #[test]
+    fn maps_translated_characters() {
+        let text = "\x1b(0q\x1b(B\x1b[31mab";
+        let slices = v3::categorise_text(text);
+        let map = OffsetMap::new(&slices);
+        assert_eq!(v3::construct_text_no_codes(&slices), "─ab");
+
+        let raw: Vec<_> = (0..=5).map(|v| map.to_raw(v).unwrap()).collect();
+        assert_eq!(raw, [3, 3, 3, 12, 13, 14]);
+        assert_eq!(map.to_visible(3), Some(0));
+        assert_eq!(map.to_visible(4), Some(3)); // the end of "─"
+        assert_eq!(map.to_visible(5), None);
+        assert_eq!(map.to_visible(6), None);
+        assert_eq!(map.to_visible(12), Some(3));
+        assert_eq!(map.to_visible(14), Some(5));
+
+        assert_eq!(map.to_raw_range(0..3), Some(3..4));
+        assert_eq!(map.to_raw_range(0..5), Some(3..14));
+        assert_eq!(map.to_raw_range(3..5), Some(12..14));
+        assert_eq!(map.char_len(), 3);
+        assert_eq!(map.char_to_byte(1), Some(3));
+    }
+}
@@ -0,0 +1,308 @@
+use super::*;
+use core::fmt::{self, Display};
+
+/// The RGB colours which a terminal shows for each [`Color`], and for the default foreground and
+/// background.
+///
+/// Built-in themes are provided as constants, and custom palettes can be made by setting the fields.
+/// Only the 16 standard and bright colours vary between themes; the rest of the 256 colour
+/// palette is the same 6x6x6 colour cube and grayscale ramp in every theme.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let palette = Palette::default();
+/// assert_eq!(palette.rgb(Color::Red), (0xcd, 0, 0));
+/// assert_eq!(palette.rgb(Color::Indexed(208)), (0xff, 0x87, 0));
+/// assert_eq!(palette.rgb(Color::Rgb(1, 2, 3)), (1, 2, 3));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Palette {
+    /// The colour of text without a foreground colour.
+    pub foreground: (u8, u8, u8),
+    /// The colour behind text without a background colour.
+    pub background: (u8, u8, u8),
+    /// The 8 standard colours followed by their 8 bright variants.
+    pub colours: [(u8, u8, u8); 16],
+}
+
+impl Palette {
+    /// The default colours of xterm.
+    pub const XTERM: Palette = Palette {
+        foreground: (0xe5, 0xe5, 0xe5),
+        background: (0x00, 0x00, 0x00),
+        colours: [
+            (0x00, 0x00, 0x00),
+            (0xcd, 0x00, 0x00),
+            (0x00, 0xcd, 0x00),
+            (0xcd, 0xcd, 0x00),
+            (0x00, 0x00, 0xee),
+            (0xcd, 0x00, 0xcd),
+            (0x00, 0xcd, 0xcd),
+            (0xe5, 0xe5, 0xe5),
+            (0x7f, 0x7f, 0x7f),
+            (0xff, 0x00, 0x00),
+            (0x00, 0xff, 0x00),
+            (0xff, 0xff, 0x00),
+            (0x5c, 0x5c, 0xff),
+            (0xff, 0x00, 0xff),
+            (0x00, 0xff, 0xff),
+            (0xff, 0xff, 0xff),
+        ],
+    };
+
+    /// The colours of the VGA text mode, as used by the Linux console.
+    pub const VGA: Palette = Palette {
+        foreground: (0xaa, 0xaa, 0xaa),
+        background: (0x00, 0x00, 0x00),
+        colours: [
+            (0x00, 0x00, 0x00),
+            (0xaa, 0x00, 0x00),
+            (0x00, 0xaa, 0x00),
+            (0xaa, 0x55, 0x00),
+            (0x00, 0x00, 0xaa),
+            (0xaa, 0x00, 0xaa),
+            (0x00, 0xaa, 0xaa),
+            (0xaa, 0xaa, 0xaa),
+            (0x55, 0x55, 0x55),
+            (0xff, 0x55, 0x55),
+            (0x55, 0xff, 0x55),
+            (0xff, 0xff, 0x55),
+            (0x55, 0x55, 0xff),
+            (0xff, 0x55, 0xff),
+            (0x55, 0xff, 0xff),
+            (0xff, 0xff, 0xff),
+        ],
+    };
+
+    /// The default (Campbell) colours of the Windows 10 console.
+    pub const WINDOWS_10: Palette = Palette {
+        foreground: (0xcc, 0xcc, 0xcc),
+        background: (0x0c, 0x0c, 0x0c),
+        colours: [
+            (0x0c, 0x0c, 0x0c),
+            (0xc5, 0x0f, 0x1f),
+            (0x13, 0xa1, 0x0e),
+            (0xc1, 0x9c, 0x00),
+            (0x00, 0x37, 0xda),
+            (0x88, 0x17, 0x98),
+            (0x3a, 0x96, 0xdd),
+            (0xcc, 0xcc, 0xcc),
+            (0x76, 0x76, 0x76),
+            (0xe7, 0x48, 0x56),
+            (0x16, 0xc6, 0x0c),
+            (0xf9, 0xf1, 0xa5),
+            (0x3b, 0x78, 0xff),
+            (0xb4, 0x00, 0x9e),
+            (0x61, 0xd6, 0xd6),
+            (0xf2, 0xf2, 0xf2),
+        ],
+    };
+
+    /// The dark variant of Solarized, the bright colours are its base tones and accents.
+    pub const SOLARIZED_DARK: Palette = Palette {
+        foreground: (0x83, 0x94, 0x96),
+        background: (0x00, 0x2b, 0x36),
+        colours: [
+            (0x07, 0x36, 0x42),
+            (0xdc, 0x32, 0x2f),
+            (0x85, 0x99, 0x00),
+            (0xb5, 0x89, 0x00),
+            (0x26, 0x8b, 0xd2),
+            (0xd3, 0x36, 0x82),
+            (0x2a, 0xa1, 0x98),
+            (0xee, 0xe8, 0xd5),
+            (0x00, 0x2b, 0x36),
+            (0xcb, 0x4b, 0x16),
+            (0x58, 0x6e, 0x75),
+            (0x65, 0x7b, 0x83),
+            (0x83, 0x94, 0x96),
+            (0x6c, 0x71, 0xc4),
+            (0x93, 0xa1, 0xa1),
+            (0xfd, 0xf6, 0xe3),
+        ],
+    };
+
+    /// The light variant of Solarized, with the same colours as [`Palette::SOLARIZED_DARK`].
+    pub const SOLARIZED_LIGHT: Palette = Palette {
+        foreground: (0x65, 0x7b, 0x83),
+        background: (0xfd, 0xf6, 0xe3),
+        colours: [
+            (0x07, 0x36, 0x42),
+            (0xdc, 0x32, 0x2f),
+            (0x85, 0x99, 0x00),
+            (0xb5, 0x89, 0x00),
+            (0x26, 0x8b, 0xd2),
+            (0xd3, 0x36, 0x82),
+            (0x2a, 0xa1, 0x98),
+            (0xee, 0xe8, 0xd5),
+            (0x00, 0x2b, 0x36),
+            (0xcb, 0x4b, 0x16),
+            (0x58, 0x6e, 0x75),
+            (0x65, 0x7b, 0x83),
+            (0x83, 0x94, 0x96),
+            (0x6c, 0x71, 0xc4),
+            (0x93, 0xa1, 0xa1),
+            (0xfd, 0xf6, 0xe3),
+        ],
+    };
+
+    /// The Tango colours, as used by GNOME Terminal.
+    pub const TANGO: Palette = Palette {
+        foreground: (0xd3, 0xd7, 0xcf),
+        background: (0x2e, 0x34, 0x36),
+        colours: [
+            (0x2e, 0x34, 0x36),
+            (0xcc, 0x00, 0x00),
+            (0x4e, 0x9a, 0x06),
+            (0xc4, 0xa0, 0x00),
+            (0x34, 0x65, 0xa4),
+            (0x75, 0x50, 0x7b),
+            (0x06, 0x98, 0x9a),
+            (0xd3, 0xd7, 0xcf),
+            (0x55, 0x57, 0x53),
+            (0xef, 0x29, 0x29),
+            (0x8a, 0xe2, 0x34),
+            (0xfc, 0xe9, 0x4f),
+            (0x72, 0x9f, 0xcf),
+            (0xad, 0x7f, 0xa8),
+            (0x34, 0xe2, 0xe2),
+            (0xee, 0xee, 0xec),
+        ],
+    };
+
+    /// The RGB value of a colour.
+    ///
+    /// Indices 0-15 of the 256 colour palette use the standard and bright colours, and the rest
+    /// the xterm colour cube and grayscale ramp.
+    pub fn rgb(&self, colour: Color) -> (u8, u8, u8) {
+        const LEVELS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+        match colour {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Indexed(i @ 0..=15) => self.colours[i as usize],
+            Color::Indexed(i @ 16..=231) => {
+                let i = i - 16;
+                (
+                    LEVELS[(i / 36) as usize],
+                    LEVELS[(i / 6 % 6) as usize],
+                    LEVELS[(i % 6) as usize],
+                )
+            }
+            Color::Indexed(i) => {
+                let l = 8 + (i - 232) * 10;
+                (l, l, l)
+            }
+            colour => self.colours[standard_index(colour)],
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::XTERM
+    }
+}
+
+/// The palette index of a standard or bright colour.
+///
+/// Extended colours are folded into the 16 indices.
+pub(crate) fn standard_index(colour: Color) -> usize {
+    use Color::*;
+    match colour {
+        Black => 0,
+        Red => 1,
+        Green => 2,
+        Yellow => 3,
+        Blue => 4,
+        Magenta => 5,
+        Cyan => 6,
+        White => 7,
+        BrightBlack => 8,
+        BrightRed => 9,
+        BrightGreen => 10,
+        BrightYellow => 11,
+        BrightBlue => 12,
+        BrightMagenta => 13,
+        BrightCyan => 14,
+        BrightWhite => 15,
+        Indexed(i) => i as usize % 16,
+        Rgb(..) => 0,
+    }
+}
+
+/// The standard or bright colour at a palette index, `i` must be less than 16.
+pub(crate) fn standard_colour(i: usize) -> Color {
+    use Color::*;
+    [
+        Black,
+        Red,
+        Green,
+        Yellow,
+        Blue,
+        Magenta,
+        Cyan,
+        White,
+        BrightBlack,
+        BrightRed,
+        BrightGreen,
+        BrightYellow,
+        BrightBlue,
+        BrightMagenta,
+        BrightCyan,
+        BrightWhite,
+    ][i]
+}
+
+/// Displays a colour as a `#rrggbb` hex colour.
+pub(crate) struct Hex(pub (u8, u8, u8));
+
+impl Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (r, g, b) = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexed_colours() {
+        let p = Palette::XTERM;
+        assert_eq!(p.rgb(Color::Indexed(1)), p.rgb(Color::Red));
+        assert_eq!(p.rgb(Color::Indexed(15)), p.rgb(Color::BrightWhite));
+        assert_eq!(p.rgb(Color::Indexed(16)), (0, 0, 0));
+        assert_eq!(p.rgb(Color::Indexed(196)), (0xff, 0, 0));
+        assert_eq!(p.rgb(Color::Indexed(231)), (0xff, 0xff, 0xff));
+        assert_eq!(p.rgb(Color::Indexed(232)), (8, 8, 8));
+        assert_eq!(p.rgb(Color::Indexed(255)), (0xee, 0xee, 0xee));
+    }
+
+    #[test]
+    fn themes() {
+        for p in [
+            Palette::XTERM,
+            Palette::VGA,
+            Palette::WINDOWS_10,
+            Palette::SOLARIZED_DARK,
+            Palette::SOLARIZED_LIGHT,
+            Palette::TANGO,
+        ] {
+            assert_eq!(p.rgb(Color::Black), p.colours[0]);
+            assert_eq!(p.rgb(Color::BrightWhite), p.colours[15]);
+            assert_eq!(p.rgb(Color::Indexed(9)), p.rgb(Color::BrightRed));
+            assert_eq!(p.rgb(Color::Indexed(21)), (0, 0, 0xff));
+            assert_ne!(p.foreground, p.background);
+        }
+
+        assert_eq!(Palette::VGA.rgb(Color::Yellow), (0xaa, 0x55, 0));
+        assert_eq!(Palette::WINDOWS_10.rgb(Color::Blue), (0, 0x37, 0xda));
+        assert_eq!(
+            Palette::SOLARIZED_DARK.rgb(Color::BrightRed),
+            (0xcb, 0x4b, 0x16)
+        );
+        assert_eq!(Palette::SOLARIZED_LIGHT.background, (0xfd, 0xf6, 0xe3));
+        assert_eq!(Palette::TANGO.rgb(Color::Green), (0x4e, 0x9a, 0x06));
+    }
+}
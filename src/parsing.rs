@@ -13,16 +13,270 @@ pub struct Match<'t> {
     pub text: &'t str,
 }
 
-// ESC is 0x1b
-const CSI: &str = "\x1b[";
+/// A match within a byte slice, see [`parse_bytes_iter`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct ByteMatch<'t> {
+    /// First byte index.
+    pub start: usize,
+    /// Last byte index + 1.
+    pub end: usize,
+    /// The byte slice (ie `bytes[start..end]`).
+    pub text: &'t [u8],
+}
+
+/// The kind of escape sequence a [`Match`] is.
+///
+/// Sequences follow the [VT500 state machine](https://vt100.net/emu/dec_ansi_parser), only the
+/// 7-bit (`ESC` prefixed) forms are recognised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceKind {
+    /// Control Sequence Introducer, `ESC [`. Includes `SGR` styling and cursor movement.
+    Csi,
+    /// Operating System Command, `ESC ]`. Includes window titles and hyperlinks.
+    Osc,
+    /// Device Control String, `ESC P`.
+    Dcs,
+    /// Start Of String, `ESC X`.
+    Sos,
+    /// Privacy Message, `ESC ^`.
+    Pm,
+    /// Application Program Command, `ESC _`.
+    Apc,
+    /// Any other escape sequence, such as `ESC 7` or `ESC ( 0`.
+    Esc,
+}
+
+impl<'t> Match<'t> {
+    /// The kind of escape sequence matched.
+    ///
+    /// ```rust
+    /// use cansi::*;
+    /// let m = parse("\x1b]0;title\x07");
+    /// assert_eq!(m[0].kind(), SequenceKind::Osc);
+    /// ```
+    pub fn kind(&self) -> SequenceKind {
+        sequence_kind(self.text.as_bytes())
+    }
+
+    /// Is this a styling sequence (`SGR`)?
+    /// Only styling sequences affect categorisation, others are just removed from the text.
+    ///
+    /// ```rust
+    /// use cansi::*;
+    /// let m = parse("\x1b[1;31m\x1b[2K");
+    /// assert!(m[0].is_sgr());
+    /// assert!(!m[1].is_sgr());
+    /// ```
+    pub fn is_sgr(&self) -> bool {
+        is_sgr(self.text.as_bytes())
+    }
+}
+
+impl<'t> ByteMatch<'t> {
+    /// The kind of escape sequence matched.
+    pub fn kind(&self) -> SequenceKind {
+        sequence_kind(self.text)
+    }
+
+    /// Is this a styling sequence (`SGR`)?
+    pub fn is_sgr(&self) -> bool {
+        is_sgr(self.text)
+    }
+}
+
+pub(crate) const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
 
 #[inline(always)]
 fn terminated_byte(byte: u8) -> bool {
     (0x40..=0x7e).contains(&byte)
 }
 
+/// The outcome of scanning an escape sequence.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Scan {
+    /// The sequence ends at the _exclusive_ byte index.
+    Complete(usize),
+    /// The input ran out before the sequence was terminated.
+    Incomplete,
+}
+
+/// The most bytes of an incomplete sequence that are held over between chunks of input.
+///
+/// A stray `ESC ]` would otherwise hold back all of the output that follows it. Like a terminal
+/// with an overlong string, a sequence which grows past this is abandoned.
+#[cfg(feature = "alloc")]
+pub(crate) const MAX_PENDING: usize = 4096;
+
+/// Abandon the incomplete sequence that starts at `start`, returning where the input resumes.
+///
+/// The first `MAX_PENDING` bytes of the sequence are dropped and the rest is treated as text,
+/// moving past any UTF-8 continuation bytes so the input can still be sliced as a `str`.
+#[cfg(feature = "alloc")]
+pub(crate) fn abandon_sequence(bytes: &[u8], start: usize) -> usize {
+    let mut resume = start + MAX_PENDING;
+    while bytes.get(resume).is_some_and(|&b| b & 0xc0 == 0x80) {
+        resume += 1;
+    }
+    resume.min(bytes.len())
+}
+
+/// Scan the escape sequence that starts at `start` (which must be an `ESC` byte).
+///
+/// An `ESC` inside a sequence aborts it (the sequence ends before the `ESC`), as does
+/// `CAN` or `SUB` (which are included in the sequence).
+pub(crate) fn scan_sequence(bytes: &[u8], start: usize) -> Scan {
+    use Scan::*;
+
+    let i = start + 1;
+    let b = match bytes.get(i) {
+        Some(b) => *b,
+        None => return Incomplete,
+    };
+
+    match b {
+        b'[' => {
+            for (j, &c) in bytes.iter().enumerate().skip(i + 1) {
+                match c {
+                    ESC => return Complete(j),
+                    CAN | SUB => return Complete(j + 1),
+                    c if terminated_byte(c) => return Complete(j + 1),
+                    _ => (),
+                }
+            }
+            Incomplete
+        }
+        b']' | b'P' | b'X' | b'^' | b'_' => {
+            let bel_terminates = b == b']';
+            for (j, &c) in bytes.iter().enumerate().skip(i + 1) {
+                match c {
+                    BEL if bel_terminates => return Complete(j + 1),
+                    CAN | SUB => return Complete(j + 1),
+                    ESC => {
+                        return match bytes.get(j + 1) {
+                            Some(b'\\') => Complete(j + 2), // ST
+                            Some(_) => Complete(j),
+                            None => Incomplete,
+                        };
+                    }
+                    _ => (),
+                }
+            }
+            Incomplete
+        }
+        0x20..=0x2f => {
+            // intermediate bytes, then a final byte
+            for (j, &c) in bytes.iter().enumerate().skip(i + 1) {
+                match c {
+                    0x20..=0x2f => (),
+                    0x30..=0x7e | CAN | SUB => return Complete(j + 1),
+                    _ => return Complete(j),
+                }
+            }
+            Incomplete
+        }
+        0x30..=0x7e => Complete(i + 1),
+        // ESC followed by something that cannot continue a sequence, the ESC is consumed alone
+        _ => Complete(i),
+    }
+}
+
+/// Find the next _complete_ escape sequence at or after `from`, returning its byte range.
+pub(crate) fn next_sequence(bytes: &[u8], from: usize) -> Option<(usize, usize)> {
+    let start = from + bytes.get(from..)?.iter().position(|&b| b == ESC)?;
+    match scan_sequence(bytes, start) {
+        Scan::Complete(end) => Some((start, end)),
+        // an unterminated sequence runs to the end of the text
+        Scan::Incomplete => None,
+    }
+}
+
+pub(crate) fn sequence_kind(bytes: &[u8]) -> SequenceKind {
+    match bytes.get(1) {
+        Some(b'[') => SequenceKind::Csi,
+        Some(b']') => SequenceKind::Osc,
+        Some(b'P') => SequenceKind::Dcs,
+        Some(b'X') => SequenceKind::Sos,
+        Some(b'^') => SequenceKind::Pm,
+        Some(b'_') => SequenceKind::Apc,
+        _ => SequenceKind::Esc,
+    }
+}
+
+/// Is the sequence a `SGR` (Select Graphic Rendition) styling sequence: a complete CSI
+/// sequence with final byte `m`, with only numeric parameters (no private markers or
+/// intermediate bytes).
+pub(crate) fn is_sgr(bytes: &[u8]) -> bool {
+    match bytes {
+        [ESC, b'[', params @ .., b'm'] => params.iter().all(|b| (b'0'..=b';').contains(b)),
+        _ => false,
+    }
+}
+
+/// The parts of a `CSI` sequence, after the `ESC [`.
+#[cfg(feature = "alloc")]
+pub(crate) struct Csi<'a> {
+    /// A private marker (`<`, `=`, `>` or `?`) before the parameters, or 0.
+    pub(crate) private: u8,
+    pub(crate) params: &'a str,
+    /// The last intermediate byte, or 0.
+    pub(crate) intermediate: u8,
+    pub(crate) action: u8,
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> Csi<'a> {
+    pub(crate) fn parse(bytes: &'a [u8]) -> Self {
+        let (action, rest) = match bytes.split_last() {
+            Some((&action, rest)) => (action, rest),
+            None => (0, bytes),
+        };
+        let (private, rest) = match rest.split_first() {
+            Some((&p @ b'<'..=b'?', rest)) => (p, rest),
+            _ => (0, rest),
+        };
+        let params = rest
+            .iter()
+            .position(|b| !(b'0'..=b';').contains(b))
+            .unwrap_or(rest.len());
+        let intermediate = rest[params..].last().copied().unwrap_or(0);
+
+        Self {
+            private,
+            params: core::str::from_utf8(&rest[..params]).unwrap_or_default(),
+            intermediate,
+            action,
+        }
+    }
+
+    /// The number of parameters.
+    pub(crate) fn len(&self) -> usize {
+        if self.params.is_empty() {
+            0
+        } else {
+            self.params.split(';').count()
+        }
+    }
+
+    /// The parameter at `i`, or `default` when it is missing or 0.
+    pub(crate) fn param(&self, i: usize, default: usize) -> usize {
+        self.params
+            .split(';')
+            .nth(i)
+            .and_then(|p| p.split(':').next()?.parse().ok())
+            .filter(|&p: &usize| p != 0)
+            .unwrap_or(default)
+            .min(u16::MAX as usize)
+    }
+}
+
 /// Parses ANSI escape codes from the given text, returning a vector of `Match`.
 ///
+/// All escape sequences are matched (see [`SequenceKind`]); sequences that are not terminated
+/// by the end of the text are not matched.
+///
 /// ```rust
 /// let ansi_text = "Hello, \x1b[31;4mworld\x1b[0m!";
 /// let parsed: Vec<_> = cansi::parse(ansi_text)
@@ -34,43 +288,98 @@ fn terminated_byte(byte: u8) -> bool {
 ///     vec![(7, 14), (19, 23)],
 /// );
 /// ```
-pub fn parse(text: &str) -> Vec<Match> {
+#[cfg(feature = "alloc")]
+pub fn parse(text: &str) -> Vec<Match<'_>> {
     let mut v = Vec::with_capacity(8);
-    let csi_len = CSI.len();
+    v.extend(parse_iter(text));
+    v
+}
 
-    let mut s = text;
-    let mut start = 0;
-    let mut end = start + csi_len;
+/// Lazily parses ANSI escape codes from the given text, returning an iterator of `Match`.
+///
+/// This is the iterator version of `parse`. It does not allocate, so works without the `std` or
+/// `alloc` features.
+///
+/// ```rust
+/// let ansi_text = "Hello, \x1b[31;4mworld\x1b[0m!";
+/// let first = cansi::parse_iter(ansi_text).next().unwrap();
+/// assert_eq!((first.start, first.end), (7, 14));
+/// ```
+pub fn parse_iter(text: &str) -> MatchIter<'_> {
+    MatchIter { text, from: 0 }
+}
 
-    while end <= text.len() {
-        if s.starts_with(CSI) {
-            // start of a CSI seq
-            while end < text.len() && !terminated_byte(text.as_bytes()[end]) {
-                end += 1;
-            }
+/// An iterator over the escape sequences in text. See [`parse_iter`].
+#[derive(Debug, Clone)]
+pub struct MatchIter<'t> {
+    text: &'t str,
+    from: usize,
+}
 
-            let end = end + 1;
+impl<'t> Iterator for MatchIter<'t> {
+    type Item = Match<'t>;
 
-            if end > text.len() {
-                break;
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, end) = next_sequence(self.text.as_bytes(), self.from)?;
+        self.from = end;
+        Some(Match {
+            start,
+            end,
+            text: &self.text[start..end],
+        })
+    }
+}
 
-            v.push(Match {
-                start,
-                end,
-                text: &text[start..end],
-            });
+/// Parses ANSI escape codes from the given bytes, returning a vector of `ByteMatch`.
+///
+/// The byte counterpart of [`parse`], for input which may not be valid UTF-8.
+/// The offsets are the same as parsing the text would give.
+///
+/// ```rust
+/// let ansi_bytes = b"\xffHello, \x1b[31;4mworld\x1b[0m!";
+/// let parsed: Vec<_> = cansi::parse_bytes(ansi_bytes)
+///     .into_iter()
+///     .map(|m| (m.start, m.end))
+///     .collect();
+/// assert_eq!(
+///     parsed,
+///     vec![(8, 15), (20, 24)],
+/// );
+/// ```
+#[cfg(feature = "alloc")]
+pub fn parse_bytes(bytes: &[u8]) -> Vec<ByteMatch<'_>> {
+    let mut v = Vec::with_capacity(8);
+    v.extend(parse_bytes_iter(bytes));
+    v
+}
 
-            start = end;
-        } else {
-            start += s.chars().next().expect("non-empty-str").len_utf8();
-        }
+/// Lazily parses ANSI escape codes from the given bytes, returning an iterator of `ByteMatch`.
+///
+/// This is the iterator version of `parse_bytes`, and works without the `std` or `alloc`
+/// features.
+pub fn parse_bytes_iter(bytes: &[u8]) -> ByteMatchIter<'_> {
+    ByteMatchIter { bytes, from: 0 }
+}
 
-        s = &text[start..];
-        end = start + csi_len;
-    }
+/// An iterator over the escape sequences in bytes. See [`parse_bytes_iter`].
+#[derive(Debug, Clone)]
+pub struct ByteMatchIter<'t> {
+    bytes: &'t [u8],
+    from: usize,
+}
 
-    v
+impl<'t> Iterator for ByteMatchIter<'t> {
+    type Item = ByteMatch<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, end) = next_sequence(self.bytes, self.from)?;
+        self.from = end;
+        Some(ByteMatch {
+            start,
+            end,
+            text: &self.bytes[start..end],
+        })
+    }
 }
 
 #[cfg(test)]
@@ -125,4 +434,159 @@ mod tests {
 
         assert_eq!(x, vec![]);
     }
+
+    fn kinds(text: &str) -> Vec<(&str, SequenceKind)> {
+        parse(text)
+            .into_iter()
+            .map(|m| (m.text, m.kind()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_all_sequence_kinds() {
+        use SequenceKind::*;
+
+        assert_eq!(
+            kinds("a\x1b]0;title\x07b\x1b]2;other\x1b\\c"),
+            vec![("\x1b]0;title\x07", Osc), ("\x1b]2;other\x1b\\", Osc)]
+        );
+        assert_eq!(
+            kinds("\x1b]8;;https://x.org\x1b\\link\x1b]8;;\x1b\\"),
+            vec![
+                ("\x1b]8;;https://x.org\x1b\\", Osc),
+                ("\x1b]8;;\x1b\\", Osc)
+            ]
+        );
+        assert_eq!(
+            kinds("\x1bPq#0;2;0;0;0\x07#1\x1b\\\x1b_apc\x1b\\\x1b^pm\x1b\\\x1bXsos\x1b\\"),
+            vec![
+                ("\x1bPq#0;2;0;0;0\x07#1\x1b\\", Dcs), // BEL does not end a DCS
+                ("\x1b_apc\x1b\\", Apc),
+                ("\x1b^pm\x1b\\", Pm),
+                ("\x1bXsos\x1b\\", Sos),
+            ]
+        );
+        assert_eq!(
+            kinds("\x1b7a\x1b8\x1b(0lqk\x1b(B\x1bc\x1b#8\x1b[2K"),
+            vec![
+                ("\x1b7", Esc),
+                ("\x1b8", Esc),
+                ("\x1b(0", Esc),
+                ("\x1b(B", Esc),
+                ("\x1bc", Esc),
+                ("\x1b#8", Esc),
+                ("\x1b[2K", Csi),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_aborted_sequences() {
+        use SequenceKind::*;
+
+        // ESC aborts a sequence in progress
+        assert_eq!(
+            kinds("\x1b[31\x1b[1m\x1b]0;t\x1b[0m"),
+            vec![
+                ("\x1b[31", Csi),
+                ("\x1b[1m", Csi),
+                ("\x1b]0;t", Osc),
+                ("\x1b[0m", Csi)
+            ]
+        );
+        // CAN and SUB abort and are consumed
+        assert_eq!(
+            kinds("\x1b[3\x18a\x1b]0\x1ab"),
+            vec![("\x1b[3\x18", Csi), ("\x1b]0\x1a", Osc)]
+        );
+        // lone ESC followed by something else
+        assert_eq!(
+            kinds("\x1b\x1b\n\x1b🌍"),
+            vec![("\x1b", Esc), ("\x1b", Esc), ("\x1b", Esc)]
+        );
+        // unterminated
+        assert_eq!(kinds("a\x1b]0;title"), vec![]);
+        assert_eq!(kinds("a\x1b]0;title\x1b"), vec![]);
+        assert_eq!(kinds("a\x1b("), vec![]);
+        assert_eq!(kinds("a\x1b"), vec![]);
+    }
+
+    #[test]
+    fn sgr_sequences() {
+        assert!(is_sgr(b"\x1b[31m"));
+        assert!(is_sgr(b"\x1b[m"));
+        assert!(is_sgr(b"\x1b[38:2::1:2:3m"));
+        assert!(!is_sgr(b"\x1b[2K"));
+        assert!(!is_sgr(b"\x1b[1A"));
+        assert!(!is_sgr(b"\x1b[>4;2m")); // xterm modifyOtherKeys
+        assert!(!is_sgr(b"\x1b[1 m"));
+        assert!(!is_sgr(b"\x1b[31"));
+        assert!(!is_sgr(b"\x1b["));
+        assert!(!is_sgr(b"\x1b]0;m"));
+    }
+
+    #[test]
+    fn parse_bytes_test() {
+        let t = "👋, \x1b[31;4m🌍\x1b[0m!";
+        let text: Vec<_> = parse(t).into_iter().map(|m| (m.start, m.end)).collect();
+        let bytes: Vec<_> = parse_bytes(t.as_bytes())
+            .into_iter()
+            .map(|m| (m.start, m.end))
+            .collect();
+        assert_eq!(text, bytes);
+
+        // truncated multibyte char before the escape
+        assert_eq!(
+            parse_bytes(b"\xf0\x9f\x1b[1m\xe9\x1b]0;\xff\x07"),
+            vec![
+                ByteMatch {
+                    start: 2,
+                    end: 6,
+                    text: b"\x1b[1m"
+                },
+                ByteMatch {
+                    start: 7,
+                    end: 13,
+                    text: b"\x1b]0;\xff\x07"
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_iter_matches_vec() {
+        let t = "👋, \x1b[31;4m🌍\x1b]0;t\x07\x1b[0m!\x1b[";
+        assert_eq!(parse_iter(t).collect::<Vec<_>>(), parse(t));
+        assert_eq!(parse_iter("").next(), None);
+
+        let mut iter = parse_iter(t);
+        assert_eq!(iter.next().map(|m| m.text), Some("\x1b[31;4m"));
+        assert_eq!(iter.next().map(|m| m.kind()), Some(SequenceKind::Osc));
+    }
+
+    #[test]
+    fn parse_bytes_iter_matches_vec() {
+        let texts: [&[u8]; 5] = [
+            b"",
+            b"\xf0\x9f\x1b[1m\xe9\x1b]0;\xff\x07!",
+            b"\x1b[31mred\x1b[",
+            b"\x1b[0m\x1bP unterminated",
+            b"tail\x1b",
+        ];
+        for bytes in texts {
+            assert_eq!(
+                parse_bytes_iter(bytes).collect::<Vec<_>>(),
+                parse_bytes(bytes)
+            );
+        }
+
+        // the same offsets as parsing text
+        let t = "👋, \x1b[31;4m🌍\x1b]0;t\x07\x1b[0m!\x1b]8;;";
+        let bytes: Vec<_> = parse_bytes_iter(t.as_bytes())
+            .map(|m| (m.start, m.end))
+            .collect();
+        let text: Vec<_> = parse_iter(t).map(|m| (m.start, m.end)).collect();
+        assert_eq!(bytes, text);
+        assert_eq!(parse_bytes_iter(t.as_bytes()).count(), 3);
+    }
 }
@@ -0,0 +1,1093 @@
+use super::*;
+use crate::categorise::{Token, Tokens};
+use crate::charset::Charsets;
+use crate::parsing::{abandon_sequence, scan_sequence, Csi, Scan, ESC, MAX_PENDING};
+use crate::width::{char_width, graphemes};
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::VecDeque;
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+
+/// A virtual terminal screen, a grid of styled cells which escaped output is drawn onto.
+///
+/// Output which redraws itself, such as progress bars and interactive prompts, concatenates every
+/// frame when categorised. A screen instead applies the cursor movement, erasing, insertion and
+/// deletion, scrolling and styling of the output, leaving the text a terminal would show.
+///
+/// Supported are the C0 controls (carriage return, line feed, backspace, tab), the `CSI`
+/// sequences for cursor movement (`CUU`, `CUD`, `CUF`, `CUB`, `CNL`, `CPL`, `CHA`, `CUP`, `HVP`,
+/// `VPA`, `VPR`, `HPA`, `HPR`), erasing (`ED`, `EL`, `ECH`), insertion and deletion (`ICH`,
+/// `DCH`, `IL`, `DL`), scrolling (`SU`, `SD`), scroll margins (`DECSTBM`), tab stops (`HTS`,
+/// `TBC`, `CHT`, `CBT`), cursor saving (`ESC 7`, `ESC 8`, `CSI s`, `CSI u`), `IND`, `RI`, `NEL`,
+/// `RIS`, autowrap (`CSI ?7h`/`l`), origin mode (`CSI ?6h`/`l`), the alternate screen
+/// (`CSI ?1049h`/`l`), character sets (see [`decode_charsets`](crate::v3::decode_charsets)),
+/// and `SGR` styling. Other sequences are ignored. Erased cells take the current background
+/// colour, as in xterm.
+///
+/// A line feed also returns the cursor to the start of the line, as a terminal does for output
+/// of a program through the `onlcr` mode of its tty.
+///
+/// Rows scrolled off the top of the screen are dropped, unless a scrollback is kept with
+/// [`Screen::with_scrollback`]. Rows scrolled off the alternate screen, which full screen
+/// programs draw on, are never kept.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let mut screen = Screen::new(3, 20);
+/// screen.feed("Downloading  10%\r\x1b[32mDownloading 100%\x1b[0m\n");
+/// screen.feed("Done\x1b[1A\x1b[13G\x1b[1m!\x1b[K");
+///
+/// let lines = screen.lines();
+/// assert_eq!(construct_text_no_codes(&lines[0]), "Downloading !");
+/// assert_eq!(lines[0][0].fg, Some(Color::Green));
+/// assert_eq!(lines[0][1].intensity, Some(Intensity::Bold));
+/// assert_eq!(construct_text_no_codes(&lines[1]), "Done");
+/// assert!(lines[2].is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Screen {
+    rows: usize,
+    cols: usize,
+    grid: Vec<Row>,
+    cursor: Cursor,
+    /// The cursor is past the last column, and wraps before the next character.
+    wrap_next: bool,
+    autowrap: bool,
+    /// The first and last rows of the scroll region.
+    top: usize,
+    bottom: usize,
+    /// Rows are positioned within the scroll region.
+    origin: bool,
+    /// Whether each column has a tab stop.
+    tabs: Vec<bool>,
+    charsets: Charsets,
+    saved: Option<Cursor>,
+    /// Rows scrolled off the top of the main screen, oldest first.
+    scrollback: VecDeque<Row>,
+    scrollback_limit: usize,
+    /// The rows of the main screen while the alternate screen is shown.
+    main: Option<Vec<Row>>,
+    /// An escape sequence split across chunks.
+    pending: String,
+}
+
+/// The cursor position and the style it writes with.
+#[derive(Debug, Clone, Copy, Default)]
+struct Cursor {
+    row: usize,
+    col: usize,
+    style: Style,
+}
+
+impl Screen {
+    /// A blank screen of `rows` by `cols` cells, with the cursor at the top left.
+    ///
+    /// Both dimensions are at least 1.
+    pub fn new(rows: usize, cols: usize) -> Self {
+        let (rows, cols) = (rows.max(1), cols.max(1));
+        Self {
+            rows,
+            cols,
+            grid: blank_rows(rows, cols, Style::default()).collect(),
+            cursor: Cursor::default(),
+            wrap_next: false,
+            autowrap: true,
+            top: 0,
+            bottom: rows - 1,
+            origin: false,
+            tabs: (0..cols).map(|col| col % 8 == 0).collect(),
+            charsets: Charsets::default(),
+            saved: None,
+            scrollback: VecDeque::new(),
+            scrollback_limit: 0,
+            main: None,
+            pending: String::new(),
+        }
+    }
+
+    /// Keep up to `lines` rows which scroll off the top of the screen, dropping the oldest rows
+    /// past that.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use cansi::v3::*;
+    /// let mut screen = Screen::new(2, 10).with_scrollback(2);
+    /// screen.feed("one\ntwo\nthree\nfour\nfive");
+    ///
+    /// let history: Vec<_> = screen.scrollback().map(|l| construct_text_no_codes(&l)).collect();
+    /// assert_eq!(history, ["two", "three"]);
+    ///
+    /// // the alternate screen is not kept
+    /// screen.feed("\x1b[?1049hfull\nscreen\nprogram\x1b[?1049l");
+    /// assert_eq!(screen.scrollback().len(), 2);
+    /// assert_eq!(construct_text_no_codes(&screen.lines()[1]), "five");
+    /// ```
+    pub fn with_scrollback(mut self, lines: usize) -> Self {
+        self.scrollback_limit = lines;
+        self.trim_scrollback();
+        self
+    }
+
+    /// The rows which scrolled off the top of the screen, oldest first.
+    ///
+    /// The lines are the same as those of [`Screen::lines`].
+    pub fn scrollback(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = v3::CategorisedLine<'_>> + ExactSizeIterator + '_ {
+        self.scrollback.iter().map(Row::line)
+    }
+
+    /// Drop the rows kept in the scrollback.
+    pub fn clear_scrollback(&mut self) {
+        self.scrollback.clear();
+    }
+
+    /// Whether the alternate screen is shown.
+    pub fn is_alternate_screen(&self) -> bool {
+        self.main.is_some()
+    }
+
+    /// The number of rows and columns.
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// The row and column of the cursor, from zero.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.cursor.row, self.cursor.col)
+    }
+
+    /// Draw the next chunk of output onto the screen.
+    ///
+    /// An escape sequence which is split across chunks is held over until it is complete. A
+    /// sequence which is still incomplete after 4096 bytes is abandoned: those bytes are dropped
+    /// and the rest is drawn as text.
+    pub fn feed(&mut self, chunk: &str) {
+        if self.pending.is_empty() {
+            let held = self.draw_complete(chunk);
+            self.pending.push_str(&chunk[held..]);
+        } else {
+            let mut text = core::mem::take(&mut self.pending);
+            text.push_str(chunk);
+            let held = self.draw_complete(&text);
+            text.drain(..held);
+            self.pending = text;
+        }
+    }
+
+    /// The text on the screen, as a line for each row.
+    ///
+    /// Blank cells at the end of a row are trimmed unless styled, so a blank row is empty. The
+    /// slices' `start` and `end` are byte positions within the row's text.
+    pub fn lines(&self) -> Vec<v3::CategorisedLine<'_>> {
+        self.grid.iter().map(Row::line).collect()
+    }
+
+    /// Draw `text` up to an incomplete sequence at its end, returning where that sequence starts.
+    fn draw_complete(&mut self, text: &str) -> usize {
+        let mut from = 0;
+        while let Some(start) = incomplete_sequence(text.as_bytes(), from) {
+            self.draw(&text[from..start]);
+            if text.len() - start <= MAX_PENDING {
+                return start;
+            }
+            from = abandon_sequence(text.as_bytes(), start);
+        }
+        self.draw(&text[from..]);
+        text.len()
+    }
+
+    fn draw(&mut self, text: &str) {
+        let mut tokens = Tokens::with_style(text.as_bytes(), self.cursor.style);
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Text(style, lo, hi) => {
+                    self.cursor.style = style.normalised();
+                    self.text(&text[lo..hi]);
+                }
+                Token::Control(start, end) => {
+                    self.cursor.style = tokens.sgr.normalised();
+                    self.control(&text.as_bytes()[start..end]);
+                    tokens.sgr = self.cursor.style;
+                }
+            }
+        }
+        self.cursor.style = tokens.sgr.normalised();
+    }
+
+    fn text(&mut self, text: &str) {
+        for (cluster, width) in graphemes(text) {
+            let c = cluster.chars().next().unwrap_or_default();
+            match c {
+                c if c.is_control() => self.c0(c),
+                _ if width == 0 => self.combine(cluster),
+                // a single character can be drawn from another character set
+                _ if cluster.len() == c.len_utf8() => {
+                    let drawn = self.charsets.map(c);
+                    self.print(drawn.encode_utf8(&mut [0; 4]), char_width(drawn));
+                }
+                _ => self.print(cluster, width),
+            }
+        }
+    }
+
+    fn c0(&mut self, c: char) {
+        match c {
+            '\r' => self.carriage_return(),
+            '\n' | '\x0b' | '\x0c' => {
+                self.carriage_return();
+                self.line_feed();
+            }
+            '\x08' => {
+                self.cursor.col = self.cursor.col.saturating_sub(1);
+                self.wrap_next = false;
+            }
+            '\t' => self.tab_forward(1),
+            _ => {
+                self.charsets.shift(c);
+            }
+        }
+    }
+
+    fn print(&mut self, cluster: &str, width: usize) {
+        let width = width.min(self.cols);
+        if self.wrap_next || self.cursor.col + width > self.cols {
+            if self.autowrap {
+                self.carriage_return();
+                self.line_feed();
+            } else {
+                self.cursor.col = self.cols - width;
+            }
+        }
+
+        let Cursor { row, col, style } = self.cursor;
+        self.grid[row].put(col, cluster, width, style);
+
+        if col + width >= self.cols {
+            self.cursor.col = self.cols - 1;
+            self.wrap_next = self.autowrap;
+        } else {
+            self.cursor.col = col + width;
+            self.wrap_next = false;
+        }
+    }
+
+    /// Add a zero width cluster, such as a combining mark, to the last character written.
+    fn combine(&mut self, cluster: &str) {
+        let Cursor { row, col, .. } = self.cursor;
+        let col = if self.wrap_next {
+            col
+        } else {
+            col.saturating_sub(1)
+        };
+        self.grid[row].append(col, cluster);
+    }
+
+    fn carriage_return(&mut self) {
+        self.cursor.col = 0;
+        self.wrap_next = false;
+    }
+
+    /// Move down a line, scrolling at the bottom margin.
+    fn line_feed(&mut self) {
+        if self.cursor.row == self.bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+        self.wrap_next = false;
+    }
+
+    /// Move up a line, scrolling at the top margin.
+    fn reverse_line_feed(&mut self) {
+        if self.cursor.row == self.top {
+            self.scroll_down(1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+        self.wrap_next = false;
+    }
+
+    /// Move to the `n`th next tab stop, or the last column.
+    fn tab_forward(&mut self, n: usize) {
+        for _ in 0..n {
+            let col = self.cursor.col + 1;
+            self.cursor.col = (col..self.cols)
+                .find(|&c| self.tabs[c])
+                .unwrap_or(self.cols - 1);
+        }
+        self.wrap_next = false;
+    }
+
+    /// Move to the `n`th previous tab stop, or the first column.
+    fn tab_back(&mut self, n: usize) {
+        for _ in 0..n {
+            self.cursor.col = (0..self.cursor.col)
+                .rev()
+                .find(|&c| self.tabs[c])
+                .unwrap_or(0);
+        }
+        self.wrap_next = false;
+    }
+
+    /// The style of erased cells, which keep the background colour.
+    fn blank(&self) -> Style {
+        Style {
+            bg: self.cursor.style.bg,
+            ..Style::default()
+        }
+    }
+
+    /// Scroll the scroll region up, keeping the rows scrolled off the top of the main screen.
+    fn scroll_up(&mut self, n: usize) {
+        let keep = self.top == 0 && self.main.is_none() && self.scrollback_limit > 0;
+        self.delete_rows(self.top, n, keep);
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        self.insert_rows(self.top, n);
+    }
+
+    /// Delete `n` rows at `row`, moving the rows below up to the bottom margin. The deleted rows
+    /// are kept in the scrollback if `keep`.
+    fn delete_rows(&mut self, row: usize, n: usize, keep: bool) {
+        let end = self.bottom + 1;
+        let n = n.min(end - row);
+        let blank = self.blank();
+        let deleted = self.grid.drain(row..row + n);
+        if keep {
+            self.scrollback.extend(deleted);
+            self.trim_scrollback();
+        } else {
+            drop(deleted);
+        }
+        self.grid
+            .splice(end - n..end - n, blank_rows(n, self.cols, blank));
+    }
+
+    /// Insert `n` blank rows at `row`, moving the rows below down and off the bottom margin.
+    fn insert_rows(&mut self, row: usize, n: usize) {
+        let end = self.bottom + 1;
+        let n = n.min(end - row);
+        let blank = self.blank();
+        self.grid.drain(end - n..end);
+        self.grid.splice(row..row, blank_rows(n, self.cols, blank));
+    }
+
+    fn trim_scrollback(&mut self) {
+        let excess = self.scrollback.len().saturating_sub(self.scrollback_limit);
+        self.scrollback.drain(..excess);
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.col = col.min(self.cols - 1);
+        self.wrap_next = false;
+    }
+
+    /// Move to a position given by a sequence, with the row within the scroll region in origin
+    /// mode.
+    fn go_to(&mut self, row: usize, col: usize) {
+        if self.origin {
+            self.move_to((self.top + row).min(self.bottom), col);
+        } else {
+            self.move_to(row, col);
+        }
+    }
+
+    /// The row `n` rows up, stopping at the top margin if the cursor is below it.
+    fn row_up(&self, n: usize) -> usize {
+        let top = if self.cursor.row >= self.top {
+            self.top
+        } else {
+            0
+        };
+        self.cursor.row.saturating_sub(n).max(top)
+    }
+
+    /// The row `n` rows down, stopping at the bottom margin if the cursor is above it.
+    fn row_down(&self, n: usize) -> usize {
+        let bottom = if self.cursor.row <= self.bottom {
+            self.bottom
+        } else {
+            self.rows - 1
+        };
+        (self.cursor.row + n).min(bottom)
+    }
+
+    fn control(&mut self, seq: &[u8]) {
+        if self.charsets.control(seq) {
+            return;
+        }
+
+        match seq {
+            [ESC, b'[', rest @ ..] => self.csi(Csi::parse(rest)),
+            [ESC, b'7'] => self.saved = Some(self.cursor),
+            [ESC, b'8'] => self.restore_cursor(),
+            [ESC, b'D'] => self.line_feed(),
+            [ESC, b'E'] => {
+                self.carriage_return();
+                self.line_feed();
+            }
+            [ESC, b'M'] => self.reverse_line_feed(),
+            [ESC, b'H'] => self.tabs[self.cursor.col] = true,
+            [ESC, b'c'] => self.reset(),
+            _ => (),
+        }
+    }
+
+    /// Reset to a blank main screen, keeping the scrollback.
+    fn reset(&mut self) {
+        let scrollback = core::mem::take(&mut self.scrollback);
+        *self = Self {
+            scrollback,
+            ..Self::new(self.rows, self.cols).with_scrollback(self.scrollback_limit)
+        };
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved.unwrap_or_default();
+        self.cursor.style = saved.style;
+        self.move_to(saved.row, saved.col);
+    }
+
+    fn csi(&mut self, csi: Csi) {
+        let Cursor { row, col, .. } = self.cursor;
+        let n = csi.param(0, 1);
+
+        match (csi.private, csi.intermediate, csi.action) {
+            (0, 0, b'A') => self.move_to(self.row_up(n), col),
+            (0, 0, b'B' | b'e') => self.move_to(self.row_down(n), col),
+            (0, 0, b'C' | b'a') => self.move_to(row, col + n),
+            (0, 0, b'D') => self.move_to(row, col.saturating_sub(n)),
+            (0, 0, b'E') => self.move_to(self.row_down(n), 0),
+            (0, 0, b'F') => self.move_to(self.row_up(n), 0),
+            (0, 0, b'G' | b'`') => self.move_to(row, n - 1),
+            (0, 0, b'H' | b'f') => self.go_to(n - 1, csi.param(1, 1) - 1),
+            (0, 0, b'd') => self.go_to(n - 1, col),
+            (0, 0, b'I') => self.tab_forward(n),
+            (0, 0, b'Z') => self.tab_back(n),
+            (0, 0, b'g') => match csi.param(0, 0) {
+                0 => self.tabs[col] = false,
+                3 => self.tabs.fill(false),
+                _ => (),
+            },
+            (0, 0, b'r') => self.set_margins(&csi),
+            (0, 0, b'J') => self.erase_display(csi.param(0, 0)),
+            (0, 0, b'K') => self.erase_line(csi.param(0, 0)),
+            (0, 0, b'X') => self.erase(row, col, col + n),
+            (0, 0, b'@') => {
+                let blank = self.blank();
+                self.grid[row].insert(col, n, blank);
+                self.wrap_next = false;
+            }
+            (0, 0, b'P') => {
+                let blank = self.blank();
+                self.grid[row].delete(col, n, blank);
+                self.wrap_next = false;
+            }
+            (0, 0, b'L') => self.insert_lines(n),
+            (0, 0, b'M') => self.delete_lines(n),
+            (0, 0, b'S') => self.scroll_up(n),
+            (0, 0, b'T') if csi.len() <= 1 => self.scroll_down(n),
+            (0, 0, b's') if csi.len() == 0 => self.saved = Some(self.cursor),
+            (0, 0, b'u') => self.restore_cursor(),
+            (b'?', 0, b'h') => self.set_modes(&csi, true),
+            (b'?', 0, b'l') => self.set_modes(&csi, false),
+            _ => (),
+        }
+    }
+
+    fn set_modes(&mut self, csi: &Csi, on: bool) {
+        for i in 0..csi.len() {
+            match csi.param(i, 0) {
+                7 => {
+                    self.autowrap = on;
+                    self.wrap_next = false;
+                }
+                6 => {
+                    self.origin = on;
+                    self.go_to(0, 0);
+                }
+                1049 => self.alternate_screen(on),
+                _ => (),
+            }
+        }
+    }
+
+    /// Set the scroll region and move home, ignoring regions of less than two rows.
+    fn set_margins(&mut self, csi: &Csi) {
+        let top = csi.param(0, 1) - 1;
+        let bottom = csi.param(1, self.rows).min(self.rows) - 1;
+        if top < bottom {
+            self.top = top;
+            self.bottom = bottom;
+            self.go_to(0, 0);
+        }
+    }
+
+    /// Switch to a blank alternate screen, saving the cursor, or back to the main screen,
+    /// restoring it.
+    fn alternate_screen(&mut self, on: bool) {
+        if on && self.main.is_none() {
+            self.saved = Some(self.cursor);
+            let alternate = blank_rows(self.rows, self.cols, Style::default()).collect();
+            self.main = Some(core::mem::replace(&mut self.grid, alternate));
+        } else if !on {
+            if let Some(main) = self.main.take() {
+                self.grid = main;
+                self.restore_cursor();
+            }
+        }
+    }
+
+    fn erase(&mut self, row: usize, from: usize, to: usize) {
+        let blank = self.blank();
+        self.grid[row].fill(from, to.min(self.cols), blank);
+        self.wrap_next = false;
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let Cursor { row, col, .. } = self.cursor;
+        let (above, below) = match mode {
+            0 => {
+                self.erase(row, col, self.cols);
+                (row + 1..self.rows, row + 1..row + 1)
+            }
+            1 => {
+                self.erase(row, 0, col + 1);
+                (0..row, row..row)
+            }
+            2 => (0..self.rows, 0..0),
+            3 => {
+                self.clear_scrollback();
+                return;
+            }
+            _ => return,
+        };
+        for r in above.chain(below) {
+            self.erase(r, 0, self.cols);
+        }
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let Cursor { row, col, .. } = self.cursor;
+        match mode {
+            0 => self.erase(row, col, self.cols),
+            1 => self.erase(row, 0, col + 1),
+            2 => self.erase(row, 0, self.cols),
+            _ => (),
+        }
+    }
+
+    /// Insert lines at the cursor when it is within the scroll region.
+    fn insert_lines(&mut self, n: usize) {
+        let row = self.cursor.row;
+        if (self.top..=self.bottom).contains(&row) {
+            self.insert_rows(row, n);
+        }
+        self.carriage_return();
+    }
+
+    /// Delete lines at the cursor when it is within the scroll region.
+    fn delete_lines(&mut self, n: usize) {
+        let row = self.cursor.row;
+        if (self.top..=self.bottom).contains(&row) {
+            self.delete_rows(row, n, false);
+        }
+        self.carriage_return();
+    }
+}
+
+fn blank_rows(n: usize, cols: usize, style: Style) -> impl Iterator<Item = Row> {
+    (0..n).map(move |_| Row::blank(cols, style))
+}
+
+/// The start of an escape sequence after `from` which is not yet terminated.
+fn incomplete_sequence(bytes: &[u8], mut from: usize) -> Option<usize> {
+    loop {
+        let start = from + bytes[from..].iter().position(|&b| b == ESC)?;
+        match scan_sequence(bytes, start) {
+            Scan::Complete(end) => from = end,
+            Scan::Incomplete => return Some(start),
+        }
+    }
+}
+
+/// A row of cells, with the text of the cells held together so it can be sliced into lines.
+#[derive(Debug, Clone)]
+struct Row {
+    text: String,
+    cells: Vec<Cell>,
+}
+
+/// A cell of a row. The second half of a wide character is a cell with no text and no width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    /// The byte range of the cell's text in the row.
+    start: usize,
+    len: usize,
+    width: usize,
+    style: Style,
+}
+
+impl Row {
+    fn blank(cols: usize, style: Style) -> Self {
+        Self {
+            text: " ".repeat(cols),
+            cells: (0..cols)
+                .map(|start| Cell {
+                    start,
+                    len: 1,
+                    width: 1,
+                    style,
+                })
+                .collect(),
+        }
+    }
+
+    fn cell_text(&self, cell: &Cell) -> &str {
+        &self.text[cell.start..cell.start + cell.len]
+    }
+
+    /// Replace the text of the cell at `col`.
+    fn set(&mut self, col: usize, text: &str, width: usize, style: Style) {
+        let cell = self.cells[col];
+        self.text
+            .replace_range(cell.start..cell.start + cell.len, text);
+        self.cells[col] = Cell {
+            len: text.len(),
+            width,
+            style,
+            ..cell
+        };
+        for c in &mut self.cells[col + 1..] {
+            c.start = c.start + text.len() - cell.len;
+        }
+    }
+
+    /// Add text to the end of the cell at `col`.
+    fn append(&mut self, col: usize, text: &str) {
+        let cell = self.cells[col];
+        self.text.insert_str(cell.start + cell.len, text);
+        self.cells[col].len += text.len();
+        for c in &mut self.cells[col + 1..] {
+            c.start += text.len();
+        }
+    }
+
+    /// Write a character `width` cells wide at `col`, blanking what is left of any wide character
+    /// it overwrites half of.
+    fn put(&mut self, col: usize, text: &str, width: usize, style: Style) {
+        for c in col..col + width {
+            self.split_wide(c);
+        }
+        self.set(col, text, width, style);
+        if width == 2 {
+            self.set(col + 1, "", 0, style);
+        }
+    }
+
+    fn split_wide(&mut self, col: usize) {
+        let (first, second) = match self.cells[col].width {
+            0 if col > 0 => (col - 1, col),
+            2 if col + 1 < self.cells.len() => (col, col + 1),
+            _ => return,
+        };
+        for c in [first, second] {
+            let style = self.cells[c].style;
+            self.set(c, " ", 1, style);
+        }
+    }
+
+    /// Blank the cells from `from` up to `to`.
+    fn fill(&mut self, from: usize, to: usize, style: Style) {
+        if from < to {
+            self.edit(|cells| {
+                for cell in &mut cells[from..to] {
+                    *cell = (" ".into(), 1, style);
+                }
+            });
+        }
+    }
+
+    /// Insert `n` blank cells at `col`, moving the rest right and off the end of the row.
+    fn insert(&mut self, col: usize, n: usize, style: Style) {
+        let cols = self.cells.len();
+        let n = n.min(cols - col);
+        self.edit(|cells| {
+            cells.splice(col..col, (0..n).map(|_| (" ".into(), 1, style)));
+            cells.truncate(cols);
+        });
+    }
+
+    /// Delete `n` cells at `col`, moving the rest left and filling the end of the row with blanks.
+    fn delete(&mut self, col: usize, n: usize, style: Style) {
+        let n = n.min(self.cells.len() - col);
+        self.edit(|cells| {
+            cells.drain(col..col + n);
+            cells.extend((0..n).map(|_| (" ".into(), 1, style)));
+        });
+    }
+
+    /// Rebuild the row after editing its cells, blanking the halves of split wide characters.
+    fn edit<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Vec<(String, usize, Style)>),
+    {
+        let mut cells: Vec<_> = self
+            .cells
+            .iter()
+            .map(|c| (self.cell_text(c).to_string(), c.width, c.style))
+            .collect();
+        f(&mut cells);
+
+        for i in 0..cells.len() {
+            let split = match cells[i].1 {
+                0 => i == 0 || cells[i - 1].1 != 2,
+                2 => cells.get(i + 1).map(|c| c.1) != Some(0),
+                _ => false,
+            };
+            if split {
+                cells[i] = (" ".into(), 1, cells[i].2);
+            }
+        }
+
+        self.text.clear();
+        self.cells.clear();
+        for (text, width, style) in cells {
+            self.cells.push(Cell {
+                start: self.text.len(),
+                len: text.len(),
+                width,
+                style,
+            });
+            self.text.push_str(&text);
+        }
+    }
+
+    /// The row as slices of the same style, with unstyled blanks trimmed from the end.
+    fn line(&self) -> v3::CategorisedLine<'_> {
+        let end = self
+            .cells
+            .iter()
+            .rposition(|c| c.width == 0 || self.cell_text(c) != " " || c.style != Style::default())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+
+        let mut line: v3::CategorisedLine = Vec::new();
+        for cell in &self.cells[..end] {
+            match line.last_mut() {
+                Some(slice) if slice.style() == cell.style => {
+                    slice.end = cell.start + cell.len;
+                    slice.text = &self.text[slice.start..slice.end];
+                }
+                _ => line.push(v3::CategorisedSlice::with_sgr(
+                    cell.style,
+                    self.cell_text(cell),
+                    cell.start,
+                    cell.start + cell.len,
+                )),
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(rows: usize, cols: usize, text: &str) -> Screen {
+        let mut screen = Screen::new(rows, cols);
+        screen.feed(text);
+        screen
+    }
+
+    fn text(screen: &Screen) -> Vec<String> {
+        screen
+            .lines()
+            .iter()
+            .map(v3::construct_text_no_codes)
+            .collect()
+    }
+
+    #[test]
+    fn prints_and_wraps() {
+        let s = screen(3, 5, "hello world");
+        assert_eq!(text(&s), ["hello", " worl", "d"]);
+        assert_eq!(s.cursor(), (2, 1));
+
+        // the cursor waits at the last column
+        let s = screen(2, 5, "hello");
+        assert_eq!(s.cursor(), (0, 4));
+        assert_eq!(text(&screen(2, 5, "hello\r\n")), ["hello", ""]);
+
+        // scrolls at the bottom
+        assert_eq!(text(&screen(2, 5, "one\ntwo\nthree")), ["two", "three"]);
+
+        assert_eq!(text(&screen(1, 5, "\x1b[?7lhello world")), ["helld"]);
+    }
+
+    #[test]
+    fn overwrites() {
+        assert_eq!(
+            text(&screen(1, 10, "progress 1\rprogress 2")),
+            ["progress 2"]
+        );
+        assert_eq!(text(&screen(1, 10, "abc\x08\x08X")), ["aXc"]);
+        assert_eq!(text(&screen(1, 20, "a\tb\tc")), ["a       b       c"]);
+        assert_eq!(text(&screen(1, 10, "long line\rshort\x1b[K")), ["short"]);
+    }
+
+    #[test]
+    fn moves_cursor() {
+        let s = screen(
+            4,
+            10,
+            "\x1b[3;4Hx\x1b[Ay\x1b[2Dz\x1b[10;10H!\x1b[1;1H\x1b[2Bw",
+        );
+        assert_eq!(text(&s), ["", "   zy", "w  x", "         !"]);
+
+        let s = screen(3, 10, "abc\x1b[2Gd\x1b[3dx\x1b[Fy\x1b[2Ez");
+        assert_eq!(text(&s), ["adc", "y", "z x"]);
+
+        let s = screen(2, 10, "ab\x1b7\x1b[31m\ncd\x1b8ef");
+        assert_eq!(text(&s), ["abef", "cd"]);
+        assert_eq!(s.lines()[0].len(), 1);
+    }
+
+    #[test]
+    fn erases() {
+        let full = "aaaa\nbbbb\ncccc";
+        assert_eq!(
+            text(&screen(3, 4, &format!("{}\x1b[2;2H\x1b[J", full))),
+            ["aaaa", "b", ""]
+        );
+        assert_eq!(
+            text(&screen(3, 4, &format!("{}\x1b[2;2H\x1b[1J", full))),
+            ["", "  bb", "cccc"]
+        );
+        assert_eq!(
+            text(&screen(3, 4, &format!("{}\x1b[2J", full))),
+            ["", "", ""]
+        );
+        assert_eq!(
+            text(&screen(3, 4, &format!("{}\x1b[2;3H\x1b[1K", full))),
+            ["aaaa", "   b", "cccc"]
+        );
+        assert_eq!(
+            text(&screen(3, 4, &format!("{}\x1b[2;2H\x1b[2K", full))),
+            ["aaaa", "", "cccc"]
+        );
+        assert_eq!(text(&screen(1, 6, "abcdef\x1b[3G\x1b[2X")), ["ab  ef"]);
+
+        // erased cells keep the background colour
+        let s = screen(1, 4, "ab\x1b[44m\x1b[1G\x1b[K");
+        let lines = s.lines();
+        assert_eq!(lines[0][0].text, "    ");
+        assert_eq!(lines[0][0].bg, Some(Color::Blue));
+    }
+
+    #[test]
+    fn inserts_and_deletes() {
+        assert_eq!(text(&screen(1, 6, "abcdef\x1b[3G\x1b[2@")), ["ab  cd"]);
+        assert_eq!(text(&screen(1, 6, "abcdef\x1b[3G\x1b[2P")), ["abef"]);
+        assert_eq!(text(&screen(1, 6, "abcdef\x1b[3G\x1b[9P")), ["ab"]);
+
+        let lines = "1\n2\n3\n4";
+        assert_eq!(
+            text(&screen(4, 3, &format!("{}\x1b[2;1H\x1b[L", lines))),
+            ["1", "", "2", "3"]
+        );
+        assert_eq!(
+            text(&screen(4, 3, &format!("{}\x1b[2;1H\x1b[2M", lines))),
+            ["1", "4", "", ""]
+        );
+        assert_eq!(
+            text(&screen(4, 3, &format!("{}\x1b[S", lines))),
+            ["2", "3", "4", ""]
+        );
+        assert_eq!(
+            text(&screen(4, 3, &format!("{}\x1b[2T", lines))),
+            ["", "", "1", "2"]
+        );
+        assert_eq!(text(&screen(2, 3, "1\n2\x1b[H\x1bM")), ["", "1"]);
+    }
+
+    #[test]
+    fn styles_cells() {
+        let s = screen(2, 12, "\x1b[1mbold\x1b[0m \x1b[3;31mred\x1b[39m it");
+        let lines = s.lines();
+        assert_eq!(lines[0].len(), 4);
+        assert_eq!(lines[0][0].text, "bold");
+        assert_eq!(lines[0][0].intensity, Some(Intensity::Bold));
+        assert_eq!(lines[0][1].text, " ");
+        assert_eq!(lines[0][2].text, "red");
+        assert_eq!((lines[0][2].start, lines[0][2].end), (5, 8));
+        assert_eq!(lines[0][2].fg, Some(Color::Red));
+        assert_eq!(lines[0][3].text, " it");
+        assert_eq!(lines[0][3].italic, Some(true));
+        assert_eq!(lines[0][3].fg, None);
+
+        // the style carries over chunks and resets
+        let mut s = Screen::new(1, 10);
+        s.feed("\x1b[32ma");
+        s.feed("b\x1bc");
+        s.feed("c");
+        assert_eq!(s.lines()[0][0].style(), Style::default());
+    }
+
+    #[test]
+    fn wide_characters() {
+        let s = screen(2, 5, "a中文b");
+        assert_eq!(text(&s), ["a中文", "b"]);
+
+        // overwriting half a wide character blanks the other half
+        assert_eq!(text(&screen(1, 6, "中文字\x1b[2Gx\x1b[5Gy")), [" x文y"]);
+        assert_eq!(text(&screen(1, 6, "中文字\x1b[3G\x1b[P")), ["中 字"]);
+
+        // combining marks join the previous character
+        assert_eq!(
+            text(&screen(1, 4, "e\u{301}x\x1b[2D\u{302}")),
+            ["e\u{301}\u{302}x"]
+        );
+    }
+
+    #[test]
+    fn split_sequences() {
+        let mut s = Screen::new(1, 10);
+        s.feed("abc\x1b[");
+        assert_eq!(text(&s), ["abc"]);
+        s.feed("2D");
+        s.feed("\x1b]0;ti");
+        s.feed("tle\x07x");
+        assert_eq!(text(&s), ["axc"]);
+
+        // an unterminated sequence is abandoned rather than holding back the output
+        s.feed("\x1b]0;");
+        for _ in 0..MAX_PENDING / 8 - 1 {
+            s.feed("12345678");
+        }
+        assert_eq!(text(&s), ["axc"]);
+        s.feed("12345678");
+        assert_eq!(text(&s), ["ax5678"]);
+        s.feed("\x1b[2Gyz");
+        assert_eq!(text(&s), ["ayz678"]);
+        s.feed(&format!("\x1bP{}", "x".repeat(MAX_PENDING)));
+        s.feed("\x1b[1Gw");
+        assert_eq!(text(&s), ["wyzxx8"]);
+        assert!(s.pending.is_empty());
+    }
+
+    #[test]
+    fn keeps_scrollback() {
+        let history = |s: &Screen| -> Vec<String> {
+            s.scrollback()
+                .map(|l| v3::construct_text_no_codes(&l))
+                .collect()
+        };
+
+        assert_eq!(history(&screen(2, 5, "1\n2\n3")), Vec::<String>::new());
+
+        let mut s = Screen::new(2, 5).with_scrollback(3);
+        s.feed("1\n\x1b[31m2\x1b[0m\n3\n4");
+        assert_eq!(history(&s), ["1", "2"]);
+        assert_eq!(s.scrollback().nth(1).unwrap()[0].fg, Some(Color::Red));
+        s.feed("\x1b[2S");
+        assert_eq!(history(&s), ["2", "3", "4"]);
+        assert_eq!(text(&s), ["", ""]);
+
+        // a reset keeps the scrollback, erasing the scrollback drops it
+        s.feed("\x1bc");
+        assert_eq!(s.scrollback().len(), 3);
+        s.feed("\x1b[3J");
+        assert_eq!(s.scrollback().len(), 0);
+
+        let mut s = Screen::new(2, 5).with_scrollback(1);
+        s.feed("a\nb\x1b[?1049h");
+        assert!(s.is_alternate_screen());
+        assert_eq!(text(&s), ["", ""]);
+        s.feed("x\ny\nz\x1b[1;1H");
+        assert_eq!(text(&s), ["y", "z"]);
+        assert_eq!(s.scrollback().len(), 0);
+        s.feed("\x1b[?1049l");
+        assert!(!s.is_alternate_screen());
+        assert_eq!(text(&s), ["a", "b"]);
+        assert_eq!(s.cursor(), (1, 1));
+
+        // switching to the alternate screen again keeps the main screen
+        let mut s = Screen::new(2, 10);
+        s.feed("main1\nmain2\x1b[?1049halt\x1b[?1049hagain\x1b[?1049l");
+        assert!(!s.is_alternate_screen());
+        assert_eq!(text(&s), ["main1", "main2"]);
+    }
+
+    #[test]
+    fn scroll_regions() {
+        let lines = "1\n2\n3\n4\n5\x1b[2;4r";
+        let scrolled = |seq: &str| text(&screen(5, 3, &format!("{}{}", lines, seq)));
+        assert_eq!(scrolled("\x1b[4;1H\nx"), ["1", "3", "4", "x", "5"]);
+        assert_eq!(scrolled("\x1b[2;1H\x1bM"), ["1", "", "2", "3", "5"]);
+        assert_eq!(scrolled("\x1b[2;1H\x1b[M"), ["1", "3", "4", "", "5"]);
+        assert_eq!(scrolled("\x1b[3;1H\x1b[9L"), ["1", "2", "", "", "5"]);
+        assert_eq!(scrolled("\x1b[5;1H\x1b[L\x1b[S"), ["1", "3", "4", "", "5"]);
+        assert_eq!(scrolled("\x1b[3;3r"), scrolled(""));
+
+        // the cursor stops at the margins, and leaves the region at the bottom
+        assert_eq!(
+            scrolled("\x1b[3;1H\x1b[9Ax\x1b[9By"),
+            ["1", "x", "3", "4y", "5"]
+        );
+        assert_eq!(scrolled("\x1b[5;1H\n\nx"), ["1", "2", "3", "4", "x"]);
+
+        // origin mode positions within the region
+        assert_eq!(scrolled("\x1b[?6hx\x1b[9;2Hy"), ["1", "x", "3", "4y", "5"]);
+        assert_eq!(scrolled("\x1b[?6h\x1b[?6lx"), ["x", "2", "3", "4", "5"]);
+
+        // only rows scrolled off the top of the screen are kept
+        let mut s = Screen::new(3, 3).with_scrollback(5);
+        s.feed("1\n2\n3\x1b[2;3r\x1b[3;1H\n\x1b[r\x1b[3;1H\n");
+        let history: Vec<_> = s
+            .scrollback()
+            .map(|l| v3::construct_text_no_codes(&l))
+            .collect();
+        assert_eq!(history, ["1"]);
+        assert_eq!(text(&s), ["3", "", ""]);
+    }
+
+    #[test]
+    fn tab_stops() {
+        assert_eq!(text(&screen(1, 20, "\x1b[1;17H\x1b[2Zx")), ["x"]);
+        assert_eq!(
+            text(&screen(1, 20, "\x1b[1;2H\x1b[2Ix")),
+            ["                x"]
+        );
+        assert_eq!(
+            text(&screen(1, 20, "\x1b[9G\x1b[g\r\tx")),
+            ["                x"]
+        );
+
+        let s = screen(1, 20, "\x1b[3g\x1b[5G\x1bH\x1b[12G\x1bH\r\ta\tb\tc\x1b[Zd");
+        assert_eq!(text(&s), ["    a      d       c"]);
+    }
+
+    #[test]
+    fn draws_lines() {
+        let s = screen(
+            3,
+            6,
+            "\x1b(0lqqqqk\r\nx\x1b(B\x1b[1mok\x1b[0m\x1b(0x\r\n\x1b)0\x0emqqqqj\x0f",
+        );
+        assert_eq!(text(&s), ["┌────┐", "│ok│", "└────┘"]);
+        assert_eq!(s.lines()[1][1].text, "ok");
+
+        // a reset returns to ASCII, a single shift draws one character
+        assert_eq!(text(&screen(1, 6, "\x1b(0q\x1bcq")), ["q"]);
+        assert_eq!(text(&screen(1, 6, "\x1b*0\x1bNqq")), ["─q"]);
+    }
+}
@@ -0,0 +1,306 @@
+use super::*;
+use crate::html::write_escaped;
+use crate::palette::{Hex, Palette};
+use crate::width::{graphemes, text_width};
+use core::fmt::{self, Display, Write};
+
+/// The space around the text, in pixels.
+const PADDING: u32 = 10;
+/// The height of the window title bar, in pixels.
+const TITLE_BAR: u32 = 30;
+
+type Rgb = (u8, u8, u8);
+
+/// A wrapper around categorised lines which displays them as an SVG image of a terminal window.
+///
+/// Each character is laid out in a monospaced cell (wide characters take 2 cells), with
+/// background rectangles for `bg` colours and text runs for `fg` colours. Reversed text swaps the
+/// colours and hidden text is not drawn. Blinking is not shown in the image.
+///
+/// The lines are expected to come from [`v3::line_iter`]. Colours come from a configurable
+/// [`Palette`] ([`Palette::XTERM`] by default), and the font from [`Svg::font`].
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[32m$\x1b[0m echo 中文\n中文");
+/// let lines: Vec<_> = line_iter(&slices).collect();
+///
+/// let svg = Svg::new(&lines).font("Fira Code", 10).to_string();
+/// assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"86\" height=\"74\""));
+/// assert!(svg.contains("<text x=\"10\" y=\"50\" fill=\"#00cd00\">$</text>"));
+/// assert!(svg.contains("<text x=\"52\" y=\"50\" fill=\"#e5e5e5\">中</text>"));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Svg<'a, 'text> {
+    lines: &'a [v3::CategorisedLine<'text>],
+    palette: Palette,
+    font_family: &'a str,
+    font_size: u32,
+}
+
+impl<'a, 'text> Svg<'a, 'text> {
+    /// Render the lines with the xterm palette, in a 14 pixel `monospace` font.
+    pub fn new(lines: &'a [v3::CategorisedLine<'text>]) -> Self {
+        Svg {
+            lines,
+            palette: Palette::XTERM,
+            font_family: "monospace",
+            font_size: 14,
+        }
+    }
+
+    /// Use the `palette` colours.
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Use the font `family`, `size` pixels high.
+    ///
+    /// The font is expected to be monospaced, cells are `0.6 * size` wide and `1.2 * size` high.
+    pub fn font(mut self, family: &'a str, size: u32) -> Self {
+        self.font_family = family;
+        self.font_size = size;
+        self
+    }
+
+    /// The cell width, in tenths of a pixel.
+    fn cell_width(&self) -> u32 {
+        self.font_size * 6
+    }
+
+    /// The cell height, in tenths of a pixel.
+    fn cell_height(&self) -> u32 {
+        self.font_size * 12
+    }
+
+    /// The colours the style is drawn with, after reversing.
+    fn colours(&self, style: Style) -> (Rgb, Option<Rgb>) {
+        let fg = style.fg.map(|c| self.palette.rgb(c));
+        let bg = style.bg.map(|c| self.palette.rgb(c));
+        if style.reversed == Some(true) {
+            (
+                bg.unwrap_or(self.palette.background),
+                Some(fg.unwrap_or(self.palette.foreground)),
+            )
+        } else {
+            (fg.unwrap_or(self.palette.foreground), bg)
+        }
+    }
+}
+
+impl Display for Svg<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (cw, ch) = (self.cell_width(), self.cell_height());
+        let columns = self
+            .lines
+            .iter()
+            .map(|line| v3::slices_width(line))
+            .max()
+            .unwrap_or(0) as u32;
+
+        let left = PADDING * 10;
+        let top = (TITLE_BAR + PADDING) * 10;
+        let width = Px(left * 2 + columns * cw);
+        let height = Px(top + PADDING * 10 + self.lines.len() as u32 * ch);
+
+        write!(
+            f,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\" font-family=\"",
+            w = width,
+            h = height
+        )?;
+        write_escaped(f, self.font_family)?;
+        writeln!(
+            f,
+            "\" font-size=\"{}\" xml:space=\"preserve\">",
+            self.font_size
+        )?;
+
+        // the window and its buttons
+        writeln!(
+            f,
+            "<rect width=\"100%\" height=\"100%\" rx=\"6\" fill=\"{}\"/>",
+            Hex(self.palette.background)
+        )?;
+        for (i, colour) in ["#ff5f56", "#ffbd2e", "#27c93f"].iter().enumerate() {
+            writeln!(
+                f,
+                "<circle cx=\"{}\" cy=\"15\" r=\"6\" fill=\"{}\"/>",
+                20 + i * 20,
+                colour
+            )?;
+        }
+
+        // backgrounds are drawn first so they do not cover text reaching into the next line
+        for (row, line) in self.lines.iter().enumerate() {
+            let y = Px(top + row as u32 * ch);
+            let mut col = 0;
+            for slice in line {
+                let cells = text_width(slice.text) as u32;
+                if let (_, Some(bg)) = self.colours(slice.style()) {
+                    if cells > 0 {
+                        writeln!(
+                            f,
+                            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+                            Px(left + col * cw),
+                            y,
+                            Px(cells * cw),
+                            Px(ch),
+                            Hex(bg)
+                        )?;
+                    }
+                }
+                col += cells;
+            }
+        }
+
+        for (row, line) in self.lines.iter().enumerate() {
+            // the baseline sits a font size below the top of the cell
+            let y = Px(top + row as u32 * ch + self.font_size * 10);
+            let mut col = 0;
+            for slice in line {
+                let style = slice.style().normalised();
+                let (fg, _) = self.colours(style);
+                for (cells, run) in runs(slice.text) {
+                    if style.hidden != Some(true) && !run.trim().is_empty() {
+                        write!(
+                            f,
+                            "<text x=\"{}\" y=\"{}\" fill=\"{}\"",
+                            Px(left + col * cw),
+                            y,
+                            Hex(fg)
+                        )?;
+                        write_attributes(f, style)?;
+                        f.write_char('>')?;
+                        write_escaped(f, run)?;
+                        f.write_str("</text>\n")?;
+                    }
+                    col += cells;
+                }
+            }
+        }
+
+        f.write_str("</svg>\n")
+    }
+}
+
+/// Write the text attributes of the style, other than colours.
+fn write_attributes<W: Write>(w: &mut W, style: Style) -> fmt::Result {
+    match style.intensity {
+        Some(Intensity::Bold) => w.write_str(" font-weight=\"bold\"")?,
+        Some(Intensity::Faint) => w.write_str(" fill-opacity=\"0.5\"")?,
+        Some(Intensity::Normal) | None => (),
+    }
+    if style.italic == Some(true) {
+        w.write_str(" font-style=\"italic\"")?;
+    }
+    match (
+        style.underline == Some(true),
+        style.strikethrough == Some(true),
+    ) {
+        (true, true) => w.write_str(" text-decoration=\"underline line-through\""),
+        (true, false) => w.write_str(" text-decoration=\"underline\""),
+        (false, true) => w.write_str(" text-decoration=\"line-through\""),
+        (false, false) => Ok(()),
+    }
+}
+
+/// Split the text into runs which can be positioned as one, with the number of cells they take
+/// up. Fonts rarely draw wide characters at exactly twice the cell width, so each wide grapheme
+/// cluster is its own run.
+fn runs(text: &str) -> impl Iterator<Item = (u32, &str)> {
+    let mut rest = text;
+    core::iter::from_fn(move || {
+        let mut clusters = graphemes(rest);
+        let (first, width) = clusters.next()?;
+        let mut cells = width as u32;
+        let mut end = first.len();
+        if width != 2 {
+            for (cluster, width) in clusters {
+                if width == 2 {
+                    break;
+                }
+                cells += width as u32;
+                end += cluster.len();
+            }
+        }
+        let (run, tail) = rest.split_at(end);
+        rest = tail;
+        Some((cells, run))
+    })
+}
+
+/// Displays tenths of a pixel as pixels.
+struct Px(u32);
+
+impl Display for Px {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 % 10 {
+            0 => write!(f, "{}", self.0 / 10),
+            tenths => write!(f, "{}.{}", self.0 / 10, tenths),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v3::{categorise_text, line_iter};
+
+    fn svg(text: &str) -> String {
+        let slices = categorise_text(text);
+        let lines: Vec<_> = line_iter(&slices).collect();
+        Svg::new(&lines).to_string()
+    }
+
+    #[test]
+    fn splits_runs() {
+        let split: Vec<_> = runs("ab中文e\u{301}f🎉").collect();
+        assert_eq!(
+            split,
+            vec![(2, "ab"), (2, "中"), (2, "文"), (2, "e\u{301}f"), (2, "🎉")]
+        );
+        assert_eq!(runs("").count(), 0);
+    }
+
+    #[test]
+    fn lays_out_cells() {
+        // 14px font: cells are 8.4 wide and 16.8 high
+        let s = svg("ab\x1b[41m中 \x1b[0m\n\x1b[1;3;4mx");
+        assert!(s.contains("width=\"62\" height=\"83.6\""), "{}", s);
+        assert!(s.contains(
+            "<rect x=\"26.8\" y=\"40\" width=\"25.2\" height=\"16.8\" fill=\"#cd0000\"/>"
+        ));
+        assert!(s.contains("<text x=\"10\" y=\"54\" fill=\"#e5e5e5\">ab</text>"));
+        assert!(s.contains("<text x=\"26.8\" y=\"54\" fill=\"#e5e5e5\">中</text>"));
+        assert!(s.contains(
+            "<text x=\"10\" y=\"70.8\" fill=\"#e5e5e5\" font-weight=\"bold\" \
+             font-style=\"italic\" text-decoration=\"underline\">x</text>"
+        ));
+    }
+
+    #[test]
+    fn palette_and_styles() {
+        let slices = categorise_text("\x1b[7mrev\x1b[0;8mhidden\x1b[0;2;38;5;16m<&>");
+        let lines: Vec<_> = line_iter(&slices).collect();
+        let palette = Palette {
+            foreground: (1, 1, 1),
+            background: (2, 2, 2),
+            ..Palette::XTERM
+        };
+        let s = Svg::new(&lines)
+            .palette(palette)
+            .font("\"Mono\"", 10)
+            .to_string();
+
+        assert!(s.contains("font-family=\"&quot;Mono&quot;\" font-size=\"10\""));
+        assert!(s.contains("rx=\"6\" fill=\"#020202\""));
+        assert!(s.contains("<rect x=\"10\" y=\"40\" width=\"18\" height=\"12\" fill=\"#010101\"/>"));
+        assert!(s.contains("<text x=\"10\" y=\"50\" fill=\"#020202\">rev</text>"));
+        assert!(!s.contains("hidden"));
+        assert!(s.contains("fill=\"#000000\" fill-opacity=\"0.5\">&lt;&amp;&gt;</text>"));
+    }
+}
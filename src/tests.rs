@@ -1,3 +1,5 @@
+#![allow(deprecated, clippy::needless_borrow, clippy::redundant_slicing)]
+
 use super::*;
 use colored::Colorize;
 
@@ -210,6 +212,47 @@ fn cover_other_parameters() {
     );
 }
 
+#[test]
+fn cover_default_colour_parameters() {
+    // 39 and 49 reset only the foreground and background colours
+    let text = "\x1b[1;31;42mtest\x1b[39mtest\x1b[49mtest";
+    let slices = v3::categorise_text(text);
+    assert_eq!(
+        slices[1],
+        v3::CategorisedSlice {
+            text: "test",
+            start: 19,
+            end: 23,
+            fg: None,
+            bg: Some(Color::Green),
+            intensity: Some(Intensity::Bold),
+            italic: None,
+            underline: None,
+            blink: None,
+            reversed: None,
+            hidden: None,
+            strikethrough: None
+        }
+    );
+    assert_eq!(
+        slices[2],
+        v3::CategorisedSlice {
+            text: "test",
+            start: 28,
+            end: 32,
+            fg: None,
+            bg: None,
+            intensity: Some(Intensity::Bold),
+            italic: None,
+            underline: None,
+            blink: None,
+            reversed: None,
+            hidden: None,
+            strikethrough: None
+        }
+    );
+}
+
 #[test]
 fn split_on_new_line_tests() {
     fn fn_as_str(s: &str) -> (&str, Option<&str>) {
@@ -273,6 +316,7 @@ fn clone_style_test() {
 
 #[test]
 fn line_iter_test() {
+    colored::control::set_override(true);
     let mut green = CategorisedSlice::default_style("", 0, 0);
     let mut red = CategorisedSlice::default_style("", 0, 0);
     green.fg_colour = Color::Green;
@@ -396,6 +440,7 @@ fn line_iter_test() {
 
 #[test]
 fn line_iter_newline_starts_with_esc() {
+    colored::control::set_override(true);
     let mut green = CategorisedSlice::default_style("", 0, 0);
     green.fg_colour = Color::Green;
 
@@ -0,0 +1,204 @@
+use super::*;
+use crate::width::{graphemes, is_extend};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+
+/// Truncate the categorised slices to at most `width` columns, keeping the style of each slice.
+///
+/// Slices are cut between grapheme clusters, so a wide character or emoji sequence which would
+/// straddle the boundary is dropped rather than split. Combining marks directly after the cut,
+/// such as in a differently styled slice, are kept, but other zero width text after the cut,
+/// such as a new line, is not.
+///
+/// When the text does not fit, `ellipsis` is appended with its style, and the text is cut short
+/// enough to leave room for it. The ellipsis slice is not part of the original text, so its
+/// `start` and `end` are both the byte position of the cut. An ellipsis wider than `width` is
+/// left off. Slices which fit within `width` are returned unchanged.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[32mOK\x1b[0m: 全部完成");
+///
+/// let cut = truncate(&slices, 7, None);
+/// assert_eq!(&construct_text_with_codes(&cut), "\x1b[32mOK\x1b[0m: 全");
+///
+/// let dim = Style { intensity: Some(Intensity::Faint), ..Style::default() };
+/// let cut = truncate(&slices, 6, Some(("…", dim)));
+/// assert_eq!(&construct_text_with_codes(&cut), "\x1b[32mOK\x1b[0m: \x1b[2m…\x1b[0m");
+/// assert_eq!(slices_width(&cut), 5); // the next character is 2 columns wide
+/// ```
+pub fn truncate<'text>(
+    slices: &[v3::CategorisedSlice<'text>],
+    width: usize,
+    ellipsis: Option<(&'text str, Style)>,
+) -> v3::CategorisedSlices<'text> {
+    if v3::slices_width(slices) <= width {
+        return slices.to_vec();
+    }
+
+    let ellipsis = ellipsis
+        .map(|(text, style)| (text, style, v3::text_width(text)))
+        .filter(|&(_, _, w)| w <= width);
+    let budget = width - ellipsis.map(|(_, _, w)| w).unwrap_or(0);
+
+    let mut cut = Vec::new();
+    let mut used = 0;
+    let mut pos = slices.first().map(|s| s.start).unwrap_or(0);
+
+    for slice in slices {
+        let mut len = 0;
+        let mut full = true;
+        for (cluster, w) in graphemes(slice.text) {
+            // at the cut, only marks which extend the last cluster are kept
+            if used + w > budget || (used == budget && !cluster.starts_with(is_extend)) {
+                full = false;
+                break;
+            }
+            used += w;
+            len += cluster.len();
+        }
+
+        if full {
+            // kept whole, as a translated character's text is longer than its range
+            cut.push(*slice);
+            pos = slice.end;
+            continue;
+        }
+
+        if len > 0 {
+            cut.push(v3::CategorisedSlice {
+                text: &slice.text[..len],
+                end: slice.start + len,
+                ..*slice
+            });
+        }
+        pos = slice.start + len;
+        break;
+    }
+
+    if let Some((text, style, _)) = ellipsis {
+        cut.push(v3::CategorisedSlice::with_sgr(style, text, pos, pos));
+    }
+
+    cut
+}
+
+/// Truncate text with ANSI escape sequences to at most `width` columns, keeping its styling.
+///
+/// The text is categorised, truncated with [`truncate`], and constructed back with
+/// [`v3::construct_text_with_codes`](crate::v3::construct_text_with_codes), so the result ends
+/// with a reset if it ends styled and styles cannot bleed into whatever follows it. Escape
+/// sequences other than `SGR` are dropped.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let red = Style { fg: Some(Color::Red), ..Style::default() };
+/// assert_eq!(
+///     truncate_ansi("\x1b[1mbuilding\x1b[22m cansi v2.2.1", 12, Some(("...", red))),
+///     "\x1b[1mbuilding\x1b[0m \x1b[31m...\x1b[0m"
+/// );
+/// assert_eq!(truncate_ansi("\x1b[1mshort", 12, None), "\x1b[1mshort\x1b[0m");
+/// ```
+pub fn truncate_ansi(text: &str, width: usize, ellipsis: Option<(&str, Style)>) -> String {
+    let slices = v3::categorise_text(text);
+    v3::construct_text_with_codes(&truncate(&slices, width, ellipsis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(slices: &[v3::CategorisedSlice]) -> String {
+        v3::construct_text_no_codes(&slices.to_vec())
+    }
+
+    #[test]
+    fn truncates_slices() {
+        let slices = v3::categorise_text("ab\x1b[31mcd\x1b[32mef");
+
+        assert_eq!(truncate(&slices, 6, None), slices);
+        assert_eq!(truncate(&slices, 10, Some(("…", Style::default()))), slices);
+
+        let cut = truncate(&slices, 3, None);
+        assert_eq!(cut.len(), 2);
+        assert_eq!(cut[1].text, "c");
+        assert_eq!((cut[1].start, cut[1].end), (7, 8));
+        assert_eq!(cut[1].fg, Some(Color::Red));
+
+        let cut = truncate(&slices, 4, None);
+        assert_eq!(plain(&cut), "abcd");
+        assert_eq!(cut.len(), 2);
+
+        assert_eq!(truncate(&slices, 0, None), vec![]);
+        assert_eq!(truncate(&[], 0, None), vec![]);
+    }
+
+    #[test]
+    fn keeps_graphemes_whole() {
+        let slices = v3::categorise_text("a中b👩\u{200d}🔬c");
+        assert_eq!(plain(&truncate(&slices, 2, None)), "a");
+        assert_eq!(plain(&truncate(&slices, 3, None)), "a中");
+        assert_eq!(plain(&truncate(&slices, 5, None)), "a中b");
+        assert_eq!(plain(&truncate(&slices, 6, None)), "a中b👩\u{200d}🔬");
+
+        // a combining mark styled apart from its base
+        let slices = v3::categorise_text("ae\x1b[4m\u{301}x");
+        assert_eq!(plain(&truncate(&slices, 2, None)), "ae\u{301}");
+
+        // but not controls
+        let slices = v3::categorise_text("ab\ncdef");
+        assert_eq!(plain(&truncate(&slices, 2, None)), "ab");
+        assert_eq!(plain(&truncate(&slices, 3, None)), "ab\nc");
+
+        // translated characters keep their range
+        let slices = v3::categorise_text("\x1b(0lqk");
+        let cut = truncate(&slices, 2, Some(("…", Style::default())));
+        assert_eq!(plain(&cut), "┌…");
+        assert_eq!((cut[0].start, cut[0].end), (3, 4));
+        assert_eq!((cut[1].start, cut[1].end), (4, 4));
+        let slices = v3::categorise_text("ab\x1b[1m\t\u{301}cd");
+        assert_eq!(truncate(&slices, 2, None).len(), 1);
+    }
+
+    #[test]
+    fn appends_ellipsis() {
+        let slices = v3::categorise_text("\x1b[34mhello world");
+        let bold = Style {
+            intensity: Some(Intensity::Bold),
+            ..Style::default()
+        };
+
+        let cut = truncate(&slices, 8, Some(("...", bold)));
+        assert_eq!(plain(&cut), "hello...");
+        assert_eq!(cut[1].style(), bold);
+        assert_eq!((cut[1].start, cut[1].end), (10, 10));
+
+        // no room for any text
+        let cut = truncate(&slices, 3, Some(("...", bold)));
+        assert_eq!(plain(&cut), "...");
+
+        // too wide for the ellipsis
+        let cut = truncate(&slices, 2, Some(("...", bold)));
+        assert_eq!(plain(&cut), "he");
+    }
+
+    #[test]
+    fn truncates_ansi() {
+        assert_eq!(truncate_ansi("", 5, None), "");
+        assert_eq!(
+            truncate_ansi("\x1b[31mred \x1b[42mon green", 9, None),
+            "\x1b[31mred \x1b[42mon gr\x1b[0m"
+        );
+        assert_eq!(
+            truncate_ansi(
+                "\x1b]0;title\x07plain text",
+                5,
+                Some(("~", Style::default()))
+            ),
+            "plai~"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use super::*;
+
+/// The number of terminal columns the text takes up.
+///
+/// The text is measured by grapheme clusters, so East Asian wide characters and emoji take 2
+/// columns, while combining marks, zero width joiners and the rest of an emoji sequence take
+/// none. Escape sequences are _not_ skipped, use [`ansi_width`] for text which contains them.
+/// Control characters take no columns, including tabs and new lines, so text with them is not
+/// measured as a terminal would lay it out: measure each line on its own, with tabs expanded.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// assert_eq!(text_width("hello"), 5);
+/// assert_eq!(text_width("中文"), 4);
+/// assert_eq!(text_width("e\u{301}"), 1); // e + combining acute accent
+/// assert_eq!(text_width("👩‍🔬"), 2); // woman + zero width joiner + microscope
+/// assert_eq!(text_width("❤\u{fe0f}"), 2); // heart + emoji presentation selector
+/// assert_eq!(text_width("🇳🇿"), 2); // a pair of regional indicators
+/// assert_eq!(text_width("a\tb\n"), 2); // controls take no columns
+/// ```
+pub fn text_width(text: &str) -> usize {
+    graphemes(text).map(|(_, w)| w).sum()
+}
+
+/// The number of terminal columns the text takes up, ignoring escape sequences.
+///
+/// See [`text_width`] for how the text is measured.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// assert_eq!(ansi_width("\x1b[1;31m中文\x1b[0m and \x1b]0;title\x07text"), 13);
+/// ```
+pub fn ansi_width(text: &str) -> usize {
+    v3::categorise_iter(text).map(|s| text_width(s.text)).sum()
+}
+
+/// The number of terminal columns the categorised slices take up.
+///
+/// See [`text_width`] for how the text is measured.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[32m✔\x1b[0m 完成");
+/// assert_eq!(slices_width(&slices), 6);
+/// ```
+pub fn slices_width(slices: &[v3::CategorisedSlice]) -> usize {
+    slices.iter().map(|s| text_width(s.text)).sum()
+}
+
+/// An iterator over the grapheme clusters of text, and the columns each takes up.
+///
+/// Clusters are a close approximation of extended grapheme clusters: a character followed by
+/// any extending characters (combining marks, variation selectors, emoji modifiers and tags),
+/// characters joined by a zero width joiner, and pairs of regional indicators.
+pub(crate) fn graphemes(text: &str) -> Graphemes<'_> {
+    Graphemes { rest: text }
+}
+
+pub(crate) struct Graphemes<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = (&'a str, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chars = self.rest.char_indices().peekable();
+        let (_, first) = chars.next()?;
+        let mut width = char_width(first);
+        // nothing extends a control character
+        let mut end = if first.is_control() {
+            first.len_utf8()
+        } else {
+            self.rest.len()
+        };
+        let mut prev = first;
+        let mut regional = is_regional_indicator(first);
+
+        while let Some(&(i, c)) = chars.peek().filter(|_| !first.is_control()) {
+            let joined = prev == ZWJ && c != ZWJ;
+            let paired = regional && is_regional_indicator(c);
+            if !(is_extend(c) || joined || paired) {
+                end = i;
+                break;
+            }
+            if paired {
+                // a flag
+                width = 2;
+                regional = false;
+            }
+            if c == VS16 {
+                // emoji presentation, characters which default to text are shown as emoji
+                width = 2;
+            }
+            prev = c;
+            chars.next();
+        }
+
+        let (cluster, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some((cluster, width))
+    }
+}
+
+const ZWJ: char = '\u{200d}';
+const VS16: char = '\u{fe0f}';
+
+/// Characters which extend the preceding character into one cluster.
+pub(crate) fn is_extend(c: char) -> bool {
+    c == ZWJ || (char_width(c) == 0 && !c.is_control())
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// The number of terminal columns a character takes up on its own.
+///
+/// East Asian wide characters and emoji take 2 columns, combining marks and other zero width
+/// characters take none, as do control characters.
+pub(crate) fn char_width(c: char) -> usize {
+    let c = c as u32;
+    if c < 0x20 || (0x7f..0xa0).contains(&c) || in_ranges(c, ZERO) {
+        0
+    } else if in_ranges(c, WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Whether `c` is in one of the sorted, inclusive `ranges`.
+fn in_ranges(c: u32, ranges: &[(u32, u32)]) -> bool {
+    ranges
+        .binary_search_by(|&(lo, hi)| {
+            if hi < c {
+                core::cmp::Ordering::Less
+            } else if lo > c {
+                core::cmp::Ordering::Greater
+            } else {
+                core::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Combining marks, Hangul medial and final jamo, zero width spaces, joiners and format
+/// characters, variation selectors, emoji modifiers and tags.
+const ZERO: &[(u32, u32)] = &[
+    (0x00ad, 0x00ad),
+    (0x0300, 0x036f),
+    (0x0483, 0x0489),
+    (0x0591, 0x05bd),
+    (0x05bf, 0x05bf),
+    (0x05c1, 0x05c2),
+    (0x05c4, 0x05c5),
+    (0x05c7, 0x05c7),
+    (0x0610, 0x061a),
+    (0x064b, 0x065f),
+    (0x0670, 0x0670),
+    (0x06d6, 0x06dc),
+    (0x06df, 0x06e4),
+    (0x06e7, 0x06e8),
+    (0x06ea, 0x06ed),
+    (0x0711, 0x0711),
+    (0x0730, 0x074a),
+    (0x0900, 0x0902),
+    (0x093a, 0x093a),
+    (0x093c, 0x093c),
+    (0x0941, 0x0948),
+    (0x094d, 0x094d),
+    (0x0951, 0x0957),
+    (0x0962, 0x0963),
+    (0x0e31, 0x0e31),
+    (0x0e34, 0x0e3a),
+    (0x0e47, 0x0e4e),
+    (0x1160, 0x11ff),
+    (0x1ab0, 0x1aff),
+    (0x1dc0, 0x1dff),
+    (0x200b, 0x200f),
+    (0x202a, 0x202e),
+    (0x2060, 0x2064),
+    (0x20d0, 0x20ff),
+    (0x302a, 0x302d),
+    (0x3099, 0x309a),
+    (0xfe00, 0xfe0f),
+    (0xfe20, 0xfe2f),
+    (0xfeff, 0xfeff),
+    (0x1f3fb, 0x1f3ff),
+    (0xe0000, 0xe007f),
+    (0xe0100, 0xe01ef),
+];
+
+/// East Asian wide and full width characters, and characters with emoji presentation.
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115f),
+    (0x231a, 0x231b),
+    (0x2329, 0x232a),
+    (0x23e9, 0x23ec),
+    (0x23f0, 0x23f0),
+    (0x23f3, 0x23f3),
+    (0x25fd, 0x25fe),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x267f, 0x267f),
+    (0x2693, 0x2693),
+    (0x26a1, 0x26a1),
+    (0x26aa, 0x26ab),
+    (0x26bd, 0x26be),
+    (0x26c4, 0x26c5),
+    (0x26ce, 0x26ce),
+    (0x26d4, 0x26d4),
+    (0x26ea, 0x26ea),
+    (0x26f2, 0x26f3),
+    (0x26f5, 0x26f5),
+    (0x26fa, 0x26fa),
+    (0x26fd, 0x26fd),
+    (0x2705, 0x2705),
+    (0x270a, 0x270b),
+    (0x2728, 0x2728),
+    (0x274c, 0x274c),
+    (0x274e, 0x274e),
+    (0x2753, 0x2755),
+    (0x2757, 0x2757),
+    (0x2795, 0x2797),
+    (0x27b0, 0x27b0),
+    (0x27bf, 0x27bf),
+    (0x2b1b, 0x2b1c),
+    (0x2b50, 0x2b50),
+    (0x2b55, 0x2b55),
+    (0x2e80, 0x303e),
+    (0x3041, 0x33ff),
+    (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff),
+    (0xa000, 0xa4cf),
+    (0xa960, 0xa97f),
+    (0xac00, 0xd7a3),
+    (0xf900, 0xfaff),
+    (0xfe10, 0xfe19),
+    (0xfe30, 0xfe6f),
+    (0xff00, 0xff60),
+    (0xffe0, 0xffe6),
+    (0x16fe0, 0x16fe4),
+    (0x17000, 0x18cd5),
+    (0x1aff0, 0x1b2fb),
+    (0x1f004, 0x1f004),
+    (0x1f0cf, 0x1f0cf),
+    (0x1f18e, 0x1f18e),
+    (0x1f191, 0x1f19a),
+    (0x1f200, 0x1f202),
+    (0x1f210, 0x1f23b),
+    (0x1f240, 0x1f248),
+    (0x1f250, 0x1f251),
+    (0x1f260, 0x1f265),
+    (0x1f300, 0x1f320),
+    (0x1f32d, 0x1f335),
+    (0x1f337, 0x1f37c),
+    (0x1f37e, 0x1f393),
+    (0x1f3a0, 0x1f3ca),
+    (0x1f3cf, 0x1f3d3),
+    (0x1f3e0, 0x1f3f0),
+    (0x1f3f4, 0x1f3f4),
+    (0x1f3f8, 0x1f3fa),
+    (0x1f400, 0x1f43e),
+    (0x1f440, 0x1f440),
+    (0x1f442, 0x1f4fc),
+    (0x1f4ff, 0x1f53d),
+    (0x1f54b, 0x1f54e),
+    (0x1f550, 0x1f567),
+    (0x1f57a, 0x1f57a),
+    (0x1f595, 0x1f596),
+    (0x1f5a4, 0x1f5a4),
+    (0x1f5fb, 0x1f64f),
+    (0x1f680, 0x1f6c5),
+    (0x1f6cc, 0x1f6cc),
+    (0x1f6d0, 0x1f6d2),
+    (0x1f6d5, 0x1f6d7),
+    (0x1f6dc, 0x1f6df),
+    (0x1f6eb, 0x1f6ec),
+    (0x1f6f4, 0x1f6fc),
+    (0x1f7e0, 0x1f7eb),
+    (0x1f7f0, 0x1f7f0),
+    (0x1f90c, 0x1f93a),
+    (0x1f93c, 0x1f945),
+    (0x1f947, 0x1f9ff),
+    (0x1fa70, 0x1faff),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('é'), 1);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(char_width('\t'), 0);
+        assert_eq!(char_width('中'), 2);
+        assert_eq!(char_width('ｈ'), 2);
+        assert_eq!(char_width('🎉'), 2);
+        assert_eq!(char_width('한'), 2);
+        assert_eq!(char_width('❤'), 1);
+        assert_eq!(char_width('⌚'), 2);
+    }
+
+    #[test]
+    fn tables_sorted() {
+        for table in [ZERO, WIDE] {
+            assert!(table.iter().all(|(lo, hi)| lo <= hi));
+            assert!(table.windows(2).all(|w| w[0].1 < w[1].0));
+        }
+    }
+
+    #[test]
+    fn clusters() {
+        let clusters = |s| graphemes(s).collect::<Vec<_>>();
+
+        assert_eq!(clusters(""), vec![]);
+        assert_eq!(clusters("ab"), vec![("a", 1), ("b", 1)]);
+        assert_eq!(
+            clusters("e\u{301}\u{302}x"),
+            vec![("e\u{301}\u{302}", 1), ("x", 1)]
+        );
+        // family: man, woman, girl
+        assert_eq!(clusters("👨‍👩‍👧!"), vec![("👨‍👩‍👧", 2), ("!", 1)]);
+        // waving hand with a skin tone modifier
+        assert_eq!(clusters("👋🏽"), vec![("👋🏽", 2)]);
+        // keycap one
+        assert_eq!(
+            clusters("1\u{fe0f}\u{20e3}"),
+            vec![("1\u{fe0f}\u{20e3}", 2)]
+        );
+        // three regional indicators are a flag and a lone indicator
+        assert_eq!(clusters("🇯🇵🇫"), vec![("🇯🇵", 2), ("🇫", 1)]);
+        // controls do not join
+        assert_eq!(
+            clusters("a\n\u{301}"),
+            vec![("a", 1), ("\n", 0), ("\u{301}", 0)]
+        );
+    }
+
+    #[test]
+    fn ansi_widths() {
+        assert_eq!(ansi_width(""), 0);
+        assert_eq!(ansi_width("\x1b[31m"), 0);
+        assert_eq!(ansi_width("\x1b[31m한국어\x1b[0m"), 6);
+        assert_eq!(ansi_width("a\x1b[2Kb\x1b[1Ac"), 3);
+        assert_eq!(
+            slices_width(&v3::categorise_text("\x1b[4m👩‍💻\x1b[24m dev")),
+            6
+        );
+    }
+}
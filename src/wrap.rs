@@ -0,0 +1,474 @@
+use super::*;
+use crate::width::graphemes;
+
+/// Wraps categorised slices into lines no wider than a given width, keeping the style of each
+/// slice.
+///
+/// Each line of the text (split at `\n` or `\r\n`) is wrapped on its own. Lines break at the
+/// usual opportunities of the Unicode line breaking algorithm: after spaces, after hyphens
+/// within words, after zero width spaces, and between East Asian wide characters (but not before
+/// closing punctuation such as `。`). Spaces at a break are dropped, while spaces at the start of
+/// a line of the text are kept. Words wider than the line are broken between grapheme clusters.
+///
+/// The first wrapped row of each line of the text starts with the initial indent, and the rest
+/// with the subsequent indent. Indents are unstyled slices which are not part of the original
+/// text, so their `start` and `end` are both the byte position of the row's first slice.
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[1mUsage:\x1b[0m cansi [OPTIONS] <FILE>");
+/// let lines = Wrapper::new(16).subsequent_indent("    ").wrap(&slices);
+///
+/// let text: Vec<_> = lines.iter().map(|l| construct_text_with_codes(l)).collect();
+/// assert_eq!(text, ["\x1b[1mUsage:\x1b[0m cansi", "    [OPTIONS]", "    <FILE>"]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wrapper<'a> {
+    width: usize,
+    initial_indent: &'a str,
+    subsequent_indent: &'a str,
+}
+
+impl<'a> Wrapper<'a> {
+    /// Wrap lines to at most `width` columns, without indents.
+    pub const fn new(width: usize) -> Self {
+        Self {
+            width,
+            initial_indent: "",
+            subsequent_indent: "",
+        }
+    }
+
+    /// Start the first row of each line with `indent`.
+    pub const fn initial_indent(mut self, indent: &'a str) -> Self {
+        self.initial_indent = indent;
+        self
+    }
+
+    /// Start the rows after the first of each line with `indent`.
+    pub const fn subsequent_indent(mut self, indent: &'a str) -> Self {
+        self.subsequent_indent = indent;
+        self
+    }
+
+    /// Wrap the slices into lines.
+    ///
+    /// Rows are only wider than the width when the indent alone is wider, in which case each
+    /// row holds a single grapheme cluster.
+    pub fn wrap<'text>(
+        &self,
+        slices: &[v3::CategorisedSlice<'text>],
+    ) -> Vec<v3::CategorisedLine<'text>>
+    where
+        'a: 'text,
+    {
+        let slices = slices.to_vec();
+        let mut lines = Vec::new();
+        for line in v3::line_iter(&slices) {
+            self.wrap_line(&line, &mut lines);
+        }
+        lines
+    }
+
+    /// Wrap a line without new lines, pushing the rows onto `rows`.
+    fn wrap_line<'text>(
+        &self,
+        line: &[v3::CategorisedSlice<'text>],
+        rows: &mut Vec<v3::CategorisedLine<'text>>,
+    ) where
+        'a: 'text,
+    {
+        let clusters = clusters(line);
+        let mut rows = Rows {
+            width: self.width,
+            initial_indent: self.initial_indent,
+            subsequent_indent: self.subsequent_indent,
+            line,
+            clusters: &clusters,
+            rows,
+            first: true,
+            row: None,
+            pending: (0, 0),
+        };
+
+        let mut start = 0;
+        for end in 1..=clusters.len() {
+            if end == clusters.len() || can_break(&clusters[end - 1], &clusters[end]) {
+                rows.place(start, end);
+                start = end;
+            }
+        }
+        rows.finish();
+    }
+}
+
+/// Wrap the slices into lines no wider than `width`, see [`Wrapper`].
+///
+/// # Example
+/// ```rust
+/// # use cansi::v3::*;
+/// let slices = categorise_text("\x1b[32mgreen\x1b[0m and \x1b[34mblue\x1b[0m text");
+/// let lines = wrap(&slices, 9);
+/// assert_eq!(construct_text_no_codes(&lines[0]), "green and");
+/// assert_eq!(lines[1][0].text, "blue");
+/// assert_eq!(lines[1][0].fg, Some(Color::Blue));
+/// assert_eq!(construct_text_no_codes(&lines[1]), "blue text");
+/// ```
+pub fn wrap<'text>(
+    slices: &[v3::CategorisedSlice<'text>],
+    width: usize,
+) -> Vec<v3::CategorisedLine<'text>> {
+    Wrapper::new(width).wrap(slices)
+}
+
+/// A grapheme cluster within a line.
+struct Cluster<'text> {
+    text: &'text str,
+    /// The index of the slice the cluster is in.
+    slice: usize,
+    /// The byte position within the slice.
+    start: usize,
+    width: usize,
+}
+
+impl Cluster<'_> {
+    fn first(&self) -> char {
+        self.text.chars().next().unwrap_or_default()
+    }
+
+    fn last(&self) -> char {
+        self.text.chars().next_back().unwrap_or_default()
+    }
+
+    fn is_space(&self) -> bool {
+        is_space(self.first())
+    }
+}
+
+fn clusters<'text>(line: &[v3::CategorisedSlice<'text>]) -> Vec<Cluster<'text>> {
+    let mut clusters = Vec::new();
+    for (slice, s) in line.iter().enumerate() {
+        let mut start = 0;
+        for (text, width) in graphemes(s.text) {
+            clusters.push(Cluster {
+                text,
+                slice,
+                start,
+                width,
+            });
+            start += text.len();
+        }
+    }
+    clusters
+}
+
+/// Spaces which allow a break after them. No-break spaces do not.
+fn is_space(c: char) -> bool {
+    c.is_whitespace() && !matches!(c, '\u{a0}' | '\u{2007}' | '\u{202f}')
+}
+
+/// Whether a line can break between two clusters.
+fn can_break(prev: &Cluster, next: &Cluster) -> bool {
+    let (p, n) = (prev.last(), next.first());
+
+    if is_space(n) || next.width == 0 {
+        // spaces hang at the end of the line, and marks stay with their base
+        false
+    } else if is_space(p) || p == '\u{200b}' {
+        true
+    } else if matches!(p, '-' | '\u{2010}') {
+        // not before numbers, so negative numbers stay whole
+        n.is_alphabetic() && prev.text.len() == 1
+    } else if prev.width == 2 || next.width == 2 {
+        !is_closing(n) && !is_opening(p)
+    } else {
+        false
+    }
+}
+
+/// East Asian punctuation which does not start a line.
+fn is_closing(c: char) -> bool {
+    matches!(
+        c,
+        '、' | '。'
+            | '，'
+            | '．'
+            | '：'
+            | '；'
+            | '！'
+            | '？'
+            | '）'
+            | '」'
+            | '』'
+            | '】'
+            | '〉'
+            | '》'
+            | '〕'
+            | '〙'
+            | '〛'
+            | 'ー'
+            | 'ゝ'
+            | 'ゞ'
+            | 'ヽ'
+            | 'ヾ'
+            | '々'
+    )
+}
+
+/// East Asian punctuation which does not end a line.
+fn is_opening(c: char) -> bool {
+    matches!(
+        c,
+        '（' | '「' | '『' | '【' | '〈' | '《' | '〔' | '〘' | '〚'
+    )
+}
+
+/// Greedily fills rows with the segments of a line between break opportunities.
+struct Rows<'c, 'text> {
+    width: usize,
+    initial_indent: &'text str,
+    subsequent_indent: &'text str,
+    line: &'c [v3::CategorisedSlice<'text>],
+    clusters: &'c [Cluster<'text>],
+    rows: &'c mut Vec<v3::CategorisedLine<'text>>,
+    /// Whether the next row is the first of the line.
+    first: bool,
+    /// The range of clusters in the current row, and its width.
+    row: Option<(usize, usize, usize)>,
+    /// The end of the spaces after the current row, and their width.
+    pending: (usize, usize),
+}
+
+impl<'text> Rows<'_, 'text> {
+    fn indent(&self) -> &'text str {
+        if self.first {
+            self.initial_indent
+        } else {
+            self.subsequent_indent
+        }
+    }
+
+    fn available(&self) -> usize {
+        self.width.saturating_sub(v3::text_width(self.indent()))
+    }
+
+    fn width(&self, from: usize, to: usize) -> usize {
+        self.clusters[from..to].iter().map(|c| c.width).sum()
+    }
+
+    /// Place the segment of clusters between `start` and `end`, which may end with spaces.
+    fn place(&mut self, start: usize, end: usize) {
+        let mut content = end;
+        while content > start && self.clusters[content - 1].is_space() {
+            content -= 1;
+        }
+        let width = self.width(start, content);
+
+        match self.row {
+            Some((from, _, w)) if w + self.pending.1 + width <= self.available() => {
+                self.row = Some((from, content, w + self.pending.1 + width));
+            }
+            Some(_) => {
+                self.emit();
+                self.start_row(start, content, width);
+            }
+            None => self.start_row(start, content, width),
+        }
+
+        self.pending = (end, self.width(content, end));
+    }
+
+    /// Start a row with a segment, breaking words which are too wide between clusters.
+    fn start_row(&mut self, start: usize, end: usize, width: usize) {
+        if width <= self.available() {
+            self.row = Some((start, end, width));
+            return;
+        }
+
+        for i in start..end {
+            let w = self.clusters[i].width;
+            match self.row {
+                Some((from, _, width)) if width + w <= self.available() => {
+                    self.row = Some((from, i + 1, width + w));
+                }
+                Some(_) => {
+                    self.emit();
+                    self.row = Some((i, i + 1, w));
+                }
+                None => self.row = Some((i, i + 1, w)),
+            }
+        }
+    }
+
+    /// Finish the line, keeping its trailing spaces if they fit.
+    fn finish(&mut self) {
+        match self.row {
+            Some((from, _, w)) if w + self.pending.1 <= self.available() => {
+                self.row = Some((from, self.pending.0, w + self.pending.1));
+            }
+            Some(_) => (),
+            None => self.row = Some((0, 0, 0)),
+        }
+        self.emit();
+    }
+
+    /// Push the current row.
+    fn emit(&mut self) {
+        let (from, to, _) = match self.row.take() {
+            Some(row) => row,
+            None => return,
+        };
+
+        let pos = match self.clusters.get(from) {
+            Some(c) => self.line[c.slice].start + c.start,
+            None => self.line.first().map(|s| s.start).unwrap_or(0),
+        };
+
+        let mut row = Vec::new();
+        let indent = self.indent();
+        if !indent.is_empty() {
+            row.push(v3::CategorisedSlice::with_sgr(
+                Style::default(),
+                indent,
+                pos,
+                pos,
+            ));
+        }
+
+        let mut i = from;
+        while i < to {
+            let first = &self.clusters[i];
+            let mut len = 0;
+            while i < to && self.clusters[i].slice == first.slice {
+                len += self.clusters[i].text.len();
+                i += 1;
+            }
+
+            let slice = &self.line[first.slice];
+            if len == slice.text.len() {
+                // kept whole, as a translated character's text is longer than its range
+                row.push(*slice);
+                continue;
+            }
+            let start = slice.start + first.start;
+            row.push(v3::CategorisedSlice {
+                text: &slice.text[first.start..first.start + len],
+                start,
+                end: start + len,
+                ..*slice
+            });
+        }
+
+        self.rows.push(row);
+        self.first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(text: &str, wrapper: Wrapper) -> Vec<String> {
+        let slices = v3::categorise_text(text);
+        wrapper
+            .wrap(&slices)
+            .iter()
+            .map(v3::construct_text_no_codes)
+            .collect()
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        let w = Wrapper::new(10);
+        assert_eq!(rows("", w), Vec::<String>::new());
+        assert_eq!(rows("short", w), ["short"]);
+        assert_eq!(
+            rows("the quick brown fox jumps", w),
+            ["the quick", "brown fox", "jumps"]
+        );
+        assert_eq!(rows("exactly 10", w), ["exactly 10"]);
+        assert_eq!(rows("spaces     between", w), ["spaces", "between"]);
+        assert_eq!(rows("  indented text", w), ["  indented", "text"]);
+        assert_eq!(rows("trailing  ", w), ["trailing  "]);
+        assert_eq!(
+            rows("one\n\ntwo three four", w),
+            ["one", "", "two three", "four"]
+        );
+        assert_eq!(
+            rows("no\u{a0}break\u{a0}space", w),
+            ["no\u{a0}break\u{a0}s", "pace"]
+        );
+    }
+
+    #[test]
+    fn wraps_words_and_ideographs() {
+        let w = Wrapper::new(8);
+        assert_eq!(rows("well-known words", w), ["well-", "known", "words"]);
+        assert_eq!(rows("a -- b", Wrapper::new(3)), ["a", "--", "b"]);
+        assert_eq!(rows("abcdefghijklmnopq", w), ["abcdefgh", "ijklmnop", "q"]);
+        assert_eq!(rows("日本語のテキスト", w), ["日本語の", "テキスト"]);
+        assert_eq!(
+            rows("これは、本。", Wrapper::new(6)),
+            ["これ", "は、", "本。"]
+        );
+        assert_eq!(
+            rows("「引用」です", Wrapper::new(6)),
+            ["「引", "用」で", "す"]
+        );
+        assert_eq!(
+            rows("ab👩\u{200d}🔬cd", Wrapper::new(3)),
+            ["ab", "👩\u{200d}🔬", "cd"]
+        );
+    }
+
+    #[test]
+    fn keeps_styles() {
+        let slices = v3::categorise_text("\x1b[31mred words\x1b[0m and \x1b[4mmore");
+        let lines = Wrapper::new(8).wrap(&slices);
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0][0].text, "red");
+        assert_eq!(lines[0][0].fg, Some(Color::Red));
+        assert_eq!(lines[1][0].text, "words");
+        assert_eq!((lines[1][0].start, lines[1][0].end), (9, 14));
+        assert_eq!(lines[1][0].fg, Some(Color::Red));
+        assert_eq!(lines[1].len(), 1);
+        assert_eq!(lines[2][0].text, "and ");
+        assert_eq!(lines[2][0].fg, None);
+        assert_eq!(lines[2][1].text, "more");
+        assert_eq!(lines[2][1].underline, Some(true));
+
+        // translated characters keep their range
+        let slices = v3::categorise_text("\x1b(0lqqk");
+        let lines = Wrapper::new(2).wrap(&slices);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            (lines[1][0].text, lines[1][0].start, lines[1][0].end),
+            ("─", 5, 6)
+        );
+    }
+
+    #[test]
+    fn indents() {
+        let w = Wrapper::new(10)
+            .initial_indent("* ")
+            .subsequent_indent("  ");
+        assert_eq!(
+            rows("first item wraps\nsecond", w),
+            ["* first", "  item", "  wraps", "* second"]
+        );
+        assert_eq!(rows("", w), Vec::<String>::new());
+        assert_eq!(rows("\n", w), ["* "]);
+
+        let slices = v3::categorise_text("\x1b[32mtext");
+        let lines = w.wrap(&slices);
+        assert_eq!(lines[0][0].style(), Style::default());
+        assert_eq!((lines[0][0].start, lines[0][0].end), (5, 5));
+        assert_eq!(lines[0][1].fg, Some(Color::Green));
+
+        // the indent leaves no room
+        let w = Wrapper::new(2).initial_indent(">>>");
+        assert_eq!(rows("ab c", w), [">>>a", "b", "c"]);
+    }
+}
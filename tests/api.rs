@@ -1,3 +1,5 @@
+#![allow(deprecated, clippy::needless_borrow, clippy::println_empty_string)]
+
 extern crate cansi;
 extern crate colored;
 
@@ -18,6 +20,7 @@ fn print_bytes(bytes: &[u8]) {
 
 #[test]
 fn test_readme_code() {
+    colored::control::set_override(true);
     let v = &mut Vec::new();
     write!(
         v,
@@ -168,6 +171,7 @@ fn test_readme_code() {
 
 #[test]
 fn test_colored_function() {
+    colored::control::set_override(true);
     let test_string: &str = "test";
     let v = &mut Vec::new();
 